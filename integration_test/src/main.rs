@@ -4,10 +4,14 @@
 
 use std::str::FromStr;
 
+use nodeless_rs::amount::{FiatAmount, Sats};
+use nodeless_rs::currency::Currency;
 use nodeless_rs::paywall::Paywall;
 use nodeless_rs::store::{InvoiceRequest, InvoiceStatus};
 use nodeless_rs::webhook::{CreateWebhook, WebHookType, WebhookEvent, WebhookStatus};
+use nodeless_rs::webhook_secret::generate_secret;
 use nodeless_rs::Nodeless;
+use serde::{Deserialize, Serialize};
 use std::env;
 use url::Url;
 
@@ -29,6 +33,7 @@ async fn main() {
     let invoice_id = test_create_store_invoice(&nodeless, &store_id).await;
     test_get_store_invoice(&nodeless, &store_id, &invoice_id).await;
     test_get_store_invoice_status(&nodeless, &store_id, &invoice_id).await;
+    test_store_invoice_with_metadata(&nodeless, &store_id).await;
 
     // Transaction
     let transaction_id = test_get_transactions(&nodeless).await;
@@ -50,6 +55,7 @@ async fn main() {
     test_get_store_webhooks(&nodeless, &store_id).await;
     test_get_store_webhook(&nodeless, &store_id, &webhook_id).await;
     test_update_store_webhook(&nodeless, &store_id, &webhook_id).await;
+    test_rotate_store_webhook_secret(&nodeless, &store_id, &webhook_id).await;
     test_get_store_webhooks(&nodeless, &store_id).await;
     test_delete_store_webhook(&nodeless, &store_id, &webhook_id).await;
 
@@ -83,9 +89,9 @@ async fn test_get_store(nodeless: &Nodeless, store_id: &str) {
 }
 
 async fn test_create_store_invoice(nodeless: &Nodeless, store_id: &str) -> String {
-    let invoice_request = InvoiceRequest {
-        amount: 21.21,
-        currency: "USD".to_string(),
+    let invoice_request: InvoiceRequest = InvoiceRequest {
+        amount: FiatAmount::from_str("21.21").unwrap(),
+        currency: Currency::USD,
         buyer_email: "hi@nodeless.io".to_string(),
         redirect_url: Url::from_str("https://nodeless.io").unwrap(),
         metadata: None,
@@ -117,6 +123,38 @@ async fn test_get_store_invoice(nodeless: &Nodeless, store_id: &str, invoice_id:
     assert_eq!(None, invoice.metadata);
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct OrderMetadata {
+    order_id: String,
+}
+
+async fn test_store_invoice_with_metadata(nodeless: &Nodeless, store_id: &str) {
+    let metadata = OrderMetadata {
+        order_id: "1042".to_string(),
+    };
+    let invoice_request = InvoiceRequest::builder()
+        .amount(FiatAmount::from_str("21.21").unwrap())
+        .currency(Currency::USD)
+        .buyer_email("hi@nodeless.io")
+        .redirect_url(Url::from_str("https://nodeless.io").unwrap())
+        .build()
+        .unwrap()
+        .with_metadata(metadata.clone())
+        .unwrap();
+
+    let invoice = nodeless
+        .create_store_invoice(store_id, invoice_request)
+        .await
+        .unwrap();
+    assert_eq!(Some(metadata.clone()), invoice.metadata);
+
+    let invoice = nodeless
+        .get_store_invoice_with_metadata::<OrderMetadata>(store_id, &invoice.id.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(Some(metadata), invoice.metadata);
+}
+
 async fn test_get_store_invoice_status(nodeless: &Nodeless, store_id: &str, invoice_id: &str) {
     let status = nodeless
         .get_store_invoice_status(store_id, invoice_id)
@@ -140,7 +178,7 @@ async fn test_create_paywall(nodeless: &Nodeless) -> String {
     let paywall = Paywall {
         name: Some("Helloworld".to_string()),
         type_: nodeless_rs::paywall::PaywallType::Redirect,
-        price: 1042,
+        price: Sats(1042),
         settings: None,
         id: None,
         created_at: None,
@@ -170,7 +208,7 @@ async fn test_update_paywall(nodeless: &Nodeless, id: &str) {
     let paywall = Paywall {
         name: Some("hiworld".to_string()),
         type_: nodeless_rs::paywall::PaywallType::Redirect,
-        price: 2042,
+        price: Sats(2042),
         settings: None,
         id: None,
         created_at: None,
@@ -241,6 +279,21 @@ async fn test_update_store_webhook(nodeless: &Nodeless, store_id: &str, webhook_
     assert_eq!(res.url.unwrap(), webhook.url);
 }
 
+async fn test_rotate_store_webhook_secret(nodeless: &Nodeless, store_id: &str, webhook_id: &str) {
+    let secret = generate_secret();
+    let before = nodeless
+        .get_store_webhook(store_id, webhook_id)
+        .await
+        .unwrap();
+
+    let res = nodeless
+        .rotate_store_webhook_secret(store_id, webhook_id, &secret)
+        .await
+        .unwrap();
+    assert_eq!(before.url, res.url);
+    assert_eq!(before.events, res.events);
+}
+
 async fn test_create_paywall_webhook(nodeless: &Nodeless, id: &str) -> String {
     let webhook = CreateWebhook {
         type_: nodeless_rs::webhook::WebHookType::Paywall,
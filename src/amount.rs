@@ -0,0 +1,298 @@
+//! Amounts
+//!
+//! Fiat amounts are decimals so `21.21` stays exact. Bitcoin amounts are
+//! [`Sats`] and [`Msats`] with checked arithmetic. Fiat amounts serialize to
+//! numeric strings so no digit is lost, bitcoin amounts to json numbers.
+use std::fmt;
+use std::str::FromStr;
+
+pub use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::NodelessError;
+use crate::serde_utils::serde_decimal;
+
+/// Amount in a fiat currency
+///
+/// Serialized as a numeric string such as `"21.21"`, deserialized from a json
+/// number or numeric string.
+#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct FiatAmount(#[serde(with = "serde_decimal")] Decimal);
+
+impl FiatAmount {
+    /// Create amount, fails for negative values
+    pub fn new(amount: Decimal) -> Result<Self, NodelessError> {
+        if amount.is_sign_negative() {
+            return Err(NodelessError::InvalidAmount(format!(
+                "{amount} is negative"
+            )));
+        }
+        Ok(Self(amount))
+    }
+
+    /// Amount as decimal
+    pub fn as_decimal(&self) -> Decimal {
+        self.0
+    }
+
+    /// Number of decimal places
+    pub fn scale(&self) -> u32 {
+        self.0.scale()
+    }
+
+    /// Sum of both amounts, `None` on overflow
+    pub fn checked_add(self, other: FiatAmount) -> Option<FiatAmount> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// Difference of both amounts, `None` if it would be negative
+    pub fn checked_sub(self, other: FiatAmount) -> Option<FiatAmount> {
+        self.0
+            .checked_sub(other.0)
+            .filter(|amount| !amount.is_sign_negative())
+            .map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FiatAmount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let amount = serde_decimal::deserialize(deserializer)?;
+        Self::new(amount).map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for FiatAmount {
+    type Err = NodelessError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let amount =
+            Decimal::from_str(s).map_err(|err| NodelessError::InvalidAmount(err.to_string()))?;
+        Self::new(amount)
+    }
+}
+
+impl TryFrom<Decimal> for FiatAmount {
+    type Error = NodelessError;
+
+    fn try_from(amount: Decimal) -> Result<Self, Self::Error> {
+        Self::new(amount)
+    }
+}
+
+impl fmt::Display for FiatAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Amount in satoshis
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct Sats(pub u64);
+
+impl Sats {
+    pub const ZERO: Sats = Sats(0);
+
+    /// Amount as plain number
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Sum of both amounts, `None` on overflow
+    pub fn checked_add(self, other: Sats) -> Option<Sats> {
+        self.0.checked_add(other.0).map(Sats)
+    }
+
+    /// Difference of both amounts, `None` if it would be negative
+    pub fn checked_sub(self, other: Sats) -> Option<Sats> {
+        self.0.checked_sub(other.0).map(Sats)
+    }
+
+    /// Amount multiplied by `factor`, `None` on overflow
+    pub fn checked_mul(self, factor: u64) -> Option<Sats> {
+        self.0.checked_mul(factor).map(Sats)
+    }
+
+    /// Amount in millisatoshis, `None` on overflow
+    pub fn to_msats(self) -> Option<Msats> {
+        self.0.checked_mul(1000).map(Msats)
+    }
+}
+
+impl From<u64> for Sats {
+    fn from(sats: u64) -> Self {
+        Sats(sats)
+    }
+}
+
+impl TryFrom<Msats> for Sats {
+    type Error = NodelessError;
+
+    /// Fails for amounts that are not whole satoshis
+    fn try_from(msats: Msats) -> Result<Self, Self::Error> {
+        if msats.0 % 1000 != 0 {
+            return Err(NodelessError::InvalidAmount(format!(
+                "{msats} is not a whole number of sats"
+            )));
+        }
+        Ok(msats.to_sats_floor())
+    }
+}
+
+impl fmt::Display for Sats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} sats", self.0)
+    }
+}
+
+/// Amount in millisatoshis
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct Msats(pub u64);
+
+impl Msats {
+    pub const ZERO: Msats = Msats(0);
+
+    /// Amount as plain number
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Sum of both amounts, `None` on overflow
+    pub fn checked_add(self, other: Msats) -> Option<Msats> {
+        self.0.checked_add(other.0).map(Msats)
+    }
+
+    /// Difference of both amounts, `None` if it would be negative
+    pub fn checked_sub(self, other: Msats) -> Option<Msats> {
+        self.0.checked_sub(other.0).map(Msats)
+    }
+
+    /// Amount multiplied by `factor`, `None` on overflow
+    pub fn checked_mul(self, factor: u64) -> Option<Msats> {
+        self.0.checked_mul(factor).map(Msats)
+    }
+
+    /// Whole satoshis, rounding down
+    pub fn to_sats_floor(self) -> Sats {
+        Sats(self.0 / 1000)
+    }
+
+    /// Whole satoshis, rounding up
+    pub fn to_sats_ceil(self) -> Sats {
+        Sats(self.0.div_ceil(1000))
+    }
+}
+
+impl From<u64> for Msats {
+    fn from(msats: u64) -> Self {
+        Msats(msats)
+    }
+}
+
+impl TryFrom<Sats> for Msats {
+    type Error = NodelessError;
+
+    fn try_from(sats: Sats) -> Result<Self, Self::Error> {
+        sats.to_msats()
+            .ok_or_else(|| NodelessError::InvalidAmount(format!("{sats} overflows msats")))
+    }
+}
+
+impl fmt::Display for Msats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} msats", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sats_to_msats() {
+        assert_eq!(Sats(21).to_msats(), Some(Msats(21_000)));
+        assert_eq!(Msats::try_from(Sats(21)).unwrap(), Msats(21_000));
+        assert_eq!(
+            Sats(u64::MAX / 1000).to_msats(),
+            Some(Msats(u64::MAX / 1000 * 1000))
+        );
+        assert_eq!(Sats(u64::MAX / 1000 + 1).to_msats(), None);
+        assert!(Msats::try_from(Sats(u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn msats_to_sats() {
+        assert_eq!(Sats::try_from(Msats(21_000)).unwrap(), Sats(21));
+        assert!(Sats::try_from(Msats(21_001)).is_err());
+        assert_eq!(Msats(21_001).to_sats_floor(), Sats(21));
+        assert_eq!(Msats(21_001).to_sats_ceil(), Sats(22));
+        assert_eq!(Msats(21_000).to_sats_ceil(), Sats(21));
+        assert_eq!(Msats(u64::MAX).to_sats_ceil(), Sats(u64::MAX / 1000 + 1));
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        assert_eq!(Sats(1).checked_add(Sats(2)), Some(Sats(3)));
+        assert_eq!(Sats(u64::MAX).checked_add(Sats(1)), None);
+        assert_eq!(Sats(1).checked_sub(Sats(2)), None);
+        assert_eq!(Msats(u64::MAX).checked_mul(2), None);
+        assert_eq!(Msats(3).checked_mul(2), Some(Msats(6)));
+
+        let amount = |s: &str| FiatAmount::from_str(s).unwrap();
+        assert_eq!(
+            amount("1.10").checked_add(amount("2.05")),
+            Some(amount("3.15"))
+        );
+        assert_eq!(amount("1.10").checked_sub(amount("2.05")), None);
+    }
+
+    #[test]
+    fn fiat_amount_rejects_negative() {
+        assert!(FiatAmount::from_str("-5.5").is_err());
+        assert!(serde_json::from_str::<FiatAmount>("-5.5").is_err());
+        assert!(serde_json::from_str::<FiatAmount>("\"-5.5\"").is_err());
+    }
+
+    #[test]
+    fn fiat_amount_serde() {
+        let amount: FiatAmount = serde_json::from_str("21.21").unwrap();
+        assert_eq!(amount.as_decimal(), Decimal::from_str("21.21").unwrap());
+        assert_eq!(serde_json::to_string(&amount).unwrap(), "\"21.21\"");
+        let amount: FiatAmount = serde_json::from_str("\"0.5\"").unwrap();
+        assert_eq!(amount, FiatAmount::from_str("0.5").unwrap());
+    }
+
+    #[test]
+    fn fiat_amount_keeps_every_digit() {
+        let amount = FiatAmount::from_str("12345678901234567.89").unwrap();
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"12345678901234567.89\"");
+        assert_eq!(serde_json::from_str::<FiatAmount>(&json).unwrap(), amount);
+    }
+
+    #[test]
+    fn fiat_amount_scientific_notation() {
+        for (json, expected) in [
+            ("1e-7", "0.0000001"),
+            ("2.5E3", "2500"),
+            ("\"1e-7\"", "0.0000001"),
+        ] {
+            let amount: FiatAmount = serde_json::from_str(json).unwrap();
+            assert_eq!(
+                amount.as_decimal(),
+                Decimal::from_str(expected).unwrap(),
+                "{json}"
+            );
+        }
+        assert!(serde_json::from_str::<FiatAmount>("\"1e\"").is_err());
+    }
+}
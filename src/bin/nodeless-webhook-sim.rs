@@ -0,0 +1,194 @@
+//! Post simulated, signed Nodeless webhook deliveries to a local endpoint
+//!
+//! ```text
+//! nodeless-webhook-sim <url> <secret> [--type store] [--events new,pending_confirmation,paid]
+//!                      [--uuid <id>] [--sats 2100] [--metadata '{"orderId":"42"}']
+//!                      [--delay-ms 500]
+//! ```
+
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use nodeless_rs::amount::Sats;
+use nodeless_rs::webhook::{WebHookType, WebhookEvent};
+use nodeless_rs::webhook_simulator::{paid_sequence, WebhookSimulator};
+use serde_json::Value;
+use url::Url;
+
+const USAGE: &str = "usage: nodeless-webhook-sim <url> <secret> [--type store|paywall|donation_page|inbox] [--events new,pending_confirmation,paid] [--uuid <id>] [--sats <amount>] [--metadata <json>] [--delay-ms <ms>]";
+
+struct Args {
+    url: Url,
+    secret: String,
+    type_: WebHookType,
+    events: Vec<WebhookEvent>,
+    uuid: String,
+    sats: Sats,
+    metadata: Option<Value>,
+    delay: Duration,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    let simulator = match WebhookSimulator::new(args.url, &args.secret, args.type_) {
+        Ok(simulator) => match args.metadata {
+            Some(metadata) => simulator.with_metadata(metadata),
+            None => simulator,
+        },
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+
+    let payloads = simulator.lifecycle(&args.uuid, args.sats, &args.events);
+    for (i, payload) in payloads.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(args.delay).await;
+        }
+        match simulator.send(payload).await {
+            Ok(status) => println!("{} {} -> {}", payload.uuid, payload.status.as_str(), status),
+            Err(err) => {
+                eprintln!("{} {} -> {}", payload.uuid, payload.status.as_str(), err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Parse command line arguments, `None` if help was requested
+fn parse_args(args: Vec<String>) -> Result<Option<Args>, String> {
+    let mut positional = Vec::new();
+    let mut type_ = WebHookType::Store;
+    let mut events = paid_sequence();
+    let mut uuid = format!("sim-{}", chrono::Utc::now().timestamp_millis());
+    let mut sats = Sats(2100);
+    let mut metadata = None;
+    let mut delay = Duration::from_millis(500);
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("missing value for {name}"));
+        match arg.as_str() {
+            "--type" => {
+                type_ = match WebHookType::from(value("--type")?.as_str()) {
+                    WebHookType::Unknown(type_) => return Err(format!("unknown --type {type_}")),
+                    type_ => type_,
+                }
+            }
+            "--events" => {
+                events = value("--events")?
+                    .split(',')
+                    .map(|event| match WebhookEvent::from(event.trim()) {
+                        WebhookEvent::Unknown(event) => Err(format!("unknown event {event}")),
+                        event => Ok(event),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            "--uuid" => uuid = value("--uuid")?,
+            "--sats" => sats = Sats(value("--sats")?.parse().map_err(|_| "invalid --sats")?),
+            "--metadata" => {
+                metadata = Some(
+                    serde_json::from_str(&value("--metadata")?)
+                        .map_err(|err| format!("invalid --metadata: {err}"))?,
+                )
+            }
+            "--delay-ms" => {
+                let ms = value("--delay-ms")?
+                    .parse()
+                    .map_err(|_| "invalid --delay-ms")?;
+                delay = Duration::from_millis(ms);
+            }
+            "-h" | "--help" => return Ok(None),
+            _ => positional.push(arg),
+        }
+    }
+
+    let [url, secret]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| "expected <url> and <secret>".to_string())?;
+
+    Ok(Some(Args {
+        url: Url::from_str(&url).map_err(|err| format!("invalid url: {err}"))?,
+        secret,
+        type_,
+        events,
+        uuid,
+        sats,
+        metadata,
+        delay,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()).collect())
+            .map(|args| args.expect("help requested"))
+    }
+
+    #[test]
+    fn parses_type_and_events() {
+        let parsed = args(&[
+            "http://localhost:8080/webhook",
+            "secret",
+            "--type",
+            "paywall",
+            "--events",
+            "new, paid",
+        ])
+        .unwrap();
+        assert_eq!(parsed.type_, WebHookType::Paywall);
+        assert_eq!(parsed.events, vec![WebhookEvent::New, WebhookEvent::Paid]);
+    }
+
+    #[test]
+    fn rejects_unknown_type() {
+        let err = args(&["http://localhost:8080", "secret", "--type", "shop"]).err();
+        assert_eq!(err.as_deref(), Some("unknown --type shop"));
+    }
+
+    #[test]
+    fn rejects_unknown_event() {
+        let err = args(&["http://localhost:8080", "secret", "--events", "new,payed"]).err();
+        assert_eq!(err.as_deref(), Some("unknown event payed"));
+    }
+
+    #[test]
+    fn parses_metadata() {
+        let parsed = args(&[
+            "http://localhost:8080",
+            "secret",
+            "--metadata",
+            r#"{"orderId":"42"}"#,
+        ])
+        .unwrap();
+        assert_eq!(parsed.metadata, Some(serde_json::json!({"orderId": "42"})));
+
+        let err = args(&["http://localhost:8080", "secret", "--metadata", "{"]).err();
+        assert!(err.unwrap().starts_with("invalid --metadata"));
+    }
+
+    #[test]
+    fn help_is_not_an_error() {
+        for help in ["-h", "--help"] {
+            let parsed = parse_args(vec![help.to_string()]);
+            assert!(matches!(parsed, Ok(None)));
+        }
+    }
+}
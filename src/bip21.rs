@@ -0,0 +1,383 @@
+//! BIP21
+//!
+//! Parses and builds the `bitcoin:` uris of [`QrCodes::unified`], which
+//! combine the onchain address and lightning invoice of a store invoice.
+//!
+//! # Example
+//! ```
+//! use nodeless_rs::amount::Sats;
+//! use nodeless_rs::bip21::Bip21Uri;
+//!
+//! let uri = Bip21Uri::new("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+//!     .with_amount(Sats(21_000))
+//!     .with_label("Coffee Shop")
+//!     .with_lightning("lnbc210u1p...");
+//!
+//! assert_eq!(
+//!     uri.to_string(),
+//!     "bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq?amount=0.00021&label=Coffee%20Shop&lightning=lnbc210u1p..."
+//! );
+//! assert_eq!(uri.to_string().parse::<Bip21Uri>().unwrap(), uri);
+//! ```
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::amount::{Msats, Sats};
+use crate::error::NodelessError;
+use crate::store::{Invoice, QrCodes};
+
+/// Number of sats in one bitcoin
+const SATS_PER_BTC: u64 = 100_000_000;
+
+/// BIP21 payment uri
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bip21Uri {
+    /// Onchain address, may be empty for lightning only uris
+    pub address: String,
+    pub amount: Option<Sats>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+    /// BOLT11 invoice of the `lightning` parameter
+    pub lightning: Option<String>,
+    /// Other parameters, in order of appearance
+    pub params: Vec<(String, String)>,
+}
+
+impl Bip21Uri {
+    /// Create uri paying to `address`
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Build the unified uri of `invoice`
+    pub fn from_invoice<M>(invoice: &Invoice<M>) -> Self {
+        Self::new(&invoice.onchain_address)
+            .with_amount(invoice.sats_amount)
+            .with_lightning(&invoice.lightning_invoice)
+    }
+
+    /// Set amount
+    pub fn with_amount(mut self, amount: Sats) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    /// Set label of the recipient
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Set message describing the payment
+    pub fn with_message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Set lightning invoice
+    pub fn with_lightning(mut self, invoice: &str) -> Self {
+        self.lightning = Some(invoice.to_string());
+        self
+    }
+
+    /// Amount in bitcoin
+    pub fn amount_btc(&self) -> Option<Decimal> {
+        self.amount.map(sats_to_btc)
+    }
+
+    /// Check address, amount and lightning invoice match `invoice`
+    pub fn check_invoice<M>(&self, invoice: &Invoice<M>) -> Result<(), NodelessError> {
+        if !self.address.is_empty() && !same_address(&self.address, &invoice.onchain_address) {
+            return Err(invalid("address does not match the invoice"));
+        }
+
+        if let Some(lightning) = &self.lightning {
+            if !lightning.eq_ignore_ascii_case(&invoice.lightning_invoice) {
+                return Err(invalid("lightning invoice does not match the invoice"));
+            }
+        }
+
+        if let Some(amount) = self.amount {
+            if amount != invoice.sats_amount {
+                return Err(NodelessError::AmountMismatch {
+                    expected: Msats::try_from(invoice.sats_amount)?,
+                    found: Msats::try_from(amount)?,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Bip21Uri {
+    type Err = NodelessError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (scheme, rest) = s
+            .split_once(':')
+            .ok_or_else(|| invalid("missing bitcoin scheme"))?;
+        if !scheme.eq_ignore_ascii_case("bitcoin") {
+            return Err(invalid("missing bitcoin scheme"));
+        }
+
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut uri = Bip21Uri::new(&percent_decode(address)?);
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = percent_decode(key)?;
+            let value = percent_decode(value)?;
+
+            match key.to_lowercase().as_str() {
+                "amount" => uri.amount = Some(btc_to_sats(&value)?),
+                "label" => uri.label = Some(value),
+                "message" => uri.message = Some(value),
+                "lightning" => uri.lightning = Some(value),
+                lower if lower.starts_with("req-") => {
+                    return Err(invalid(&format!("unsupported required parameter {key}")))
+                }
+                _ => uri.params.push((key, value)),
+            }
+        }
+
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for Bip21Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bitcoin:{}", self.address)?;
+
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount_btc() {
+            params.push(("amount".to_string(), amount.to_string()));
+        }
+        if let Some(label) = &self.label {
+            params.push(("label".to_string(), label.clone()));
+        }
+        if let Some(message) = &self.message {
+            params.push(("message".to_string(), message.clone()));
+        }
+        if let Some(lightning) = &self.lightning {
+            params.push(("lightning".to_string(), lightning.clone()));
+        }
+        params.extend(self.params.iter().cloned());
+
+        for (i, (key, value)) in params.iter().enumerate() {
+            let separator = if i == 0 { '?' } else { '&' };
+            write!(
+                f,
+                "{separator}{}={}",
+                percent_encode(key),
+                percent_encode(value)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl QrCodes {
+    /// Parse the unified uri
+    pub fn unified_uri(&self) -> Result<Bip21Uri, NodelessError> {
+        Bip21Uri::from_str(&self.unified)
+    }
+}
+
+impl<M> Invoice<M> {
+    /// Parse the unified uri and check it matches the invoice
+    pub fn check_unified_uri(&self) -> Result<Bip21Uri, NodelessError> {
+        let uri = self.qr_codes.unified_uri()?;
+        uri.check_invoice(self)?;
+        Ok(uri)
+    }
+}
+
+fn sats_to_btc(sats: Sats) -> Decimal {
+    Decimal::from(sats.as_u64()) / Decimal::from(SATS_PER_BTC)
+}
+
+fn btc_to_sats(btc: &str) -> Result<Sats, NodelessError> {
+    let btc = Decimal::from_str(btc).map_err(|_| invalid("invalid amount"))?;
+    if btc.is_sign_negative() {
+        return Err(invalid("negative amount"));
+    }
+    let sats = btc
+        .checked_mul(Decimal::from(SATS_PER_BTC))
+        .ok_or_else(|| invalid("amount overflows"))?;
+    if !sats.fract().is_zero() {
+        return Err(invalid("amount has more than 8 decimal places"));
+    }
+    u64::try_from(sats)
+        .map(Sats)
+        .map_err(|_| invalid("amount overflows"))
+}
+
+/// Bech32 addresses are case insensitive, base58 ones are not
+fn same_address(a: &str, b: &str) -> bool {
+    let is_bech32 = |address: &str| {
+        let address = address.to_lowercase();
+        ["bc1", "tb1", "bcrt1"]
+            .iter()
+            .any(|prefix| address.starts_with(prefix))
+    };
+
+    if is_bech32(a) && is_bech32(b) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Result<String, NodelessError> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .ok_or_else(|| invalid("invalid percent encoding"))?;
+            let byte =
+                u8::from_str_radix(hex, 16).map_err(|_| invalid("invalid percent encoding"))?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid("invalid utf-8"))
+}
+
+fn invalid(reason: &str) -> NodelessError {
+    NodelessError::InvalidBip21(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+    fn invoice() -> Invoice {
+        serde_json::from_value(json!({
+            "id": "invoice-id",
+            "checkoutLink": null,
+            "satsAmount": 21000,
+            "status": "new",
+            "buyerEmail": "hi@nodeless.io",
+            "redirectUrl": "https://nodeless.io",
+            "metadata": [],
+            "createdAt": "2023-06-01T12:00:00.000000Z",
+            "paidAt": null,
+            "onchainAddress": ADDRESS,
+            "lightningInvoice": "lnbc210u1pinvoice",
+            "store": {
+                "id": "store-id",
+                "name": "Store",
+                "url": null,
+                "email": null,
+                "createdAt": "2023-06-01T12:00:00.000000Z"
+            },
+            "qrCodes": {
+                "unified": format!("bitcoin:{ADDRESS}?amount=0.00021&lightning=lnbc210u1pinvoice"),
+                "onchain": format!("bitcoin:{ADDRESS}"),
+                "lightning": "lightning:lnbc210u1pinvoice"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_uppercase_uri() {
+        let uri = Bip21Uri::from_str(&format!(
+            "BITCOIN:{}?AMOUNT=0.00021&LIGHTNING=LNBC210U1PINVOICE",
+            ADDRESS.to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(uri.address, ADDRESS.to_uppercase());
+        assert_eq!(uri.amount, Some(Sats(21_000)));
+        assert_eq!(uri.lightning.as_deref(), Some("LNBC210U1PINVOICE"));
+        uri.check_invoice(&invoice()).unwrap();
+    }
+
+    #[test]
+    fn decodes_percent_encoded_values() {
+        let uri = Bip21Uri::from_str(&format!(
+            "bitcoin:{ADDRESS}?label=Coffee%20Shop&message=Order%20%231042%20%E2%98%95"
+        ))
+        .unwrap();
+        assert_eq!(uri.label.as_deref(), Some("Coffee Shop"));
+        assert_eq!(uri.message.as_deref(), Some("Order #1042 ☕"));
+        assert_eq!(uri.to_string().parse::<Bip21Uri>().unwrap(), uri);
+    }
+
+    #[test]
+    fn keeps_case_of_unknown_params() {
+        let uri = Bip21Uri::from_str(&format!("bitcoin:{ADDRESS}?PayJoin=1&pj=x")).unwrap();
+        assert_eq!(
+            uri.params,
+            vec![
+                ("PayJoin".to_string(), "1".to_string()),
+                ("pj".to_string(), "x".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_required_params() {
+        assert!(Bip21Uri::from_str(&format!("bitcoin:{ADDRESS}?req-foo=1")).is_err());
+        assert!(Bip21Uri::from_str(&format!("bitcoin:{ADDRESS}?REQ-Foo=1")).is_err());
+    }
+
+    #[test]
+    fn amount_precision() {
+        let parse =
+            |amount: &str| Bip21Uri::from_str(&format!("bitcoin:{ADDRESS}?amount={amount}"));
+        assert_eq!(parse("0.00000001").unwrap().amount, Some(Sats(1)));
+        assert_eq!(parse("21").unwrap().amount, Some(Sats(2_100_000_000)));
+        assert!(parse("0.000000001").is_err());
+        assert!(parse("-1").is_err());
+        assert!(parse("abc").is_err());
+    }
+
+    #[test]
+    fn check_invoice_mismatch() {
+        let invoice = invoice();
+        let uri = invoice.check_unified_uri().unwrap();
+
+        assert!(matches!(
+            uri.clone()
+                .with_amount(Sats(21_001))
+                .check_invoice(&invoice),
+            Err(NodelessError::AmountMismatch { .. })
+        ));
+        assert!(Bip21Uri::new("bc1qotheraddress")
+            .check_invoice(&invoice)
+            .is_err());
+        assert!(uri
+            .with_lightning("lnbc1otherinvoice")
+            .check_invoice(&invoice)
+            .is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! BOLT11
+//!
+//! Decodes the lightning invoices of store invoices and paywall requests.
+//! The signature is not verified, the decoded view is meant for displaying
+//! and cross-checking invoices issued by Nodeless.
+//!
+//! # Example
+//! ```
+//! use nodeless_rs::amount::Msats;
+//! use nodeless_rs::bolt11::{Bolt11Description, Bolt11Invoice, Network};
+//!
+//! let invoice = Bolt11Invoice::decode(
+//!     "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh",
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(invoice.network, Network::Bitcoin);
+//! assert_eq!(invoice.amount, Some(Msats(250_000_000)));
+//! assert_eq!(invoice.timestamp, 1496314658);
+//! assert_eq!(invoice.expiry, 60);
+//! assert_eq!(
+//!     invoice.description,
+//!     Some(Bolt11Description::Direct("1 cup coffee".to_string()))
+//! );
+//! ```
+use std::fmt;
+use std::str::FromStr;
+
+use bech32::{u5, FromBase32};
+
+use crate::amount::{Msats, Sats};
+use crate::error::NodelessError;
+use crate::paywall::PaywallRequest;
+use crate::store::Invoice;
+
+/// Expiry in seconds of invoices without an expiry field
+pub const DEFAULT_EXPIRY: u64 = 3600;
+
+/// Minimum final cltv expiry delta of invoices without the field
+pub const DEFAULT_MIN_FINAL_CLTV_EXPIRY: u64 = 18;
+
+/// Length of the signature and recovery id in 5 bit words
+const SIGNATURE_WORDS: usize = 104;
+
+/// Length of the timestamp in 5 bit words
+const TIMESTAMP_WORDS: usize = 7;
+
+/// Bitcoin network an invoice is payable on
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Network {
+    Bitcoin,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    /// Currency prefix of invoices on the network
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Network::Bitcoin => "bc",
+            Network::Testnet => "tb",
+            Network::Signet => "tbs",
+            Network::Regtest => "bcrt",
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Network::Bitcoin => f.write_str("bitcoin"),
+            Network::Testnet => f.write_str("testnet"),
+            Network::Signet => f.write_str("signet"),
+            Network::Regtest => f.write_str("regtest"),
+        }
+    }
+}
+
+/// Description of a payment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bolt11Description {
+    /// Description text
+    Direct(String),
+    /// Hex encoded SHA256 hash of the description
+    Hash(String),
+}
+
+/// Decoded BOLT11 invoice
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bolt11Invoice {
+    pub network: Network,
+    /// `None` for invoices payable with any amount
+    pub amount: Option<Msats>,
+    /// Unix timestamp the invoice was created at
+    pub timestamp: i64,
+    /// Hex encoded payment hash
+    pub payment_hash: String,
+    /// Hex encoded payment secret
+    pub payment_secret: Option<String>,
+    pub description: Option<Bolt11Description>,
+    /// Seconds after `timestamp` the invoice expires
+    pub expiry: u64,
+    pub min_final_cltv_expiry: u64,
+    /// Hex encoded public key of the payee, if included in the invoice
+    pub payee: Option<String>,
+}
+
+impl Bolt11Invoice {
+    /// Decode `invoice`, with or without `lightning:` prefix
+    ///
+    /// The signature is not verified, so neither the payee nor the fields are
+    /// authenticated. Only rely on the result for invoices received from
+    /// Nodeless over an authenticated connection.
+    pub fn decode(invoice: &str) -> Result<Self, NodelessError> {
+        let invoice = invoice.trim();
+        let invoice = invoice
+            .strip_prefix("lightning:")
+            .or_else(|| invoice.strip_prefix("LIGHTNING:"))
+            .unwrap_or(invoice);
+
+        let (hrp, data, _) = bech32::decode(invoice).map_err(|err| invalid(&err.to_string()))?;
+        let (network, amount) = parse_hrp(&hrp)?;
+
+        if data.len() < TIMESTAMP_WORDS + SIGNATURE_WORDS {
+            return Err(invalid("too short"));
+        }
+        let timestamp = words_to_u64(&data[..TIMESTAMP_WORDS]) as i64;
+        let mut fields = &data[TIMESTAMP_WORDS..data.len() - SIGNATURE_WORDS];
+
+        let mut decoded = Self {
+            network,
+            amount,
+            timestamp,
+            payment_hash: String::new(),
+            payment_secret: None,
+            description: None,
+            expiry: DEFAULT_EXPIRY,
+            min_final_cltv_expiry: DEFAULT_MIN_FINAL_CLTV_EXPIRY,
+            payee: None,
+        };
+
+        while !fields.is_empty() {
+            if fields.len() < 3 {
+                return Err(invalid("truncated tagged field"));
+            }
+            let tag = fields[0].to_u8();
+            let length = words_to_u64(&fields[1..3]) as usize;
+            if fields.len() < 3 + length {
+                return Err(invalid("truncated tagged field"));
+            }
+            let value = &fields[3..3 + length];
+            fields = &fields[3 + length..];
+
+            match tag {
+                // p
+                1 if length == 52 => decoded.payment_hash = hex::encode(words_to_bytes(value)?),
+                // s
+                16 if length == 52 => {
+                    decoded.payment_secret = Some(hex::encode(words_to_bytes(value)?))
+                }
+                // d
+                13 => {
+                    let description = String::from_utf8(words_to_bytes(value)?)
+                        .map_err(|_| invalid("description is not utf-8"))?;
+                    decoded.description = Some(Bolt11Description::Direct(description));
+                }
+                // h
+                23 if length == 52 => {
+                    decoded.description =
+                        Some(Bolt11Description::Hash(hex::encode(words_to_bytes(value)?)))
+                }
+                // x
+                6 => decoded.expiry = words_to_u64(value),
+                // c
+                24 => decoded.min_final_cltv_expiry = words_to_u64(value),
+                // n
+                19 if length == 53 => decoded.payee = Some(hex::encode(words_to_bytes(value)?)),
+                // Fields with unexpected lengths and unknown fields are skipped
+                _ => {}
+            }
+        }
+
+        if decoded.payment_hash.is_empty() {
+            return Err(invalid("missing payment hash"));
+        }
+
+        Ok(decoded)
+    }
+
+    /// Unix timestamp the invoice expires at
+    pub fn expires_at(&self) -> i64 {
+        self.timestamp
+            .saturating_add(i64::try_from(self.expiry).unwrap_or(i64::MAX))
+    }
+
+    /// Check the invoice requests exactly `sats`
+    pub fn check_amount(&self, sats: Sats) -> Result<(), NodelessError> {
+        let expected = Msats::try_from(sats)?;
+        match self.amount {
+            Some(found) if found == expected => Ok(()),
+            Some(found) => Err(NodelessError::AmountMismatch { expected, found }),
+            None => Err(invalid("invoice has no amount")),
+        }
+    }
+}
+
+impl FromStr for Bolt11Invoice {
+    type Err = NodelessError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(s)
+    }
+}
+
+impl<M> Invoice<M> {
+    /// Decode the lightning invoice
+    pub fn decode_lightning_invoice(&self) -> Result<Bolt11Invoice, NodelessError> {
+        Bolt11Invoice::decode(&self.lightning_invoice)
+    }
+
+    /// Decode the lightning invoice and check it requests `sats_amount`
+    pub fn check_lightning_invoice(&self) -> Result<Bolt11Invoice, NodelessError> {
+        let decoded = self.decode_lightning_invoice()?;
+        decoded.check_amount(self.sats_amount)?;
+        Ok(decoded)
+    }
+}
+
+impl<M> PaywallRequest<M> {
+    /// Decode the lightning invoice
+    pub fn decode_lightning_invoice(&self) -> Result<Bolt11Invoice, NodelessError> {
+        Bolt11Invoice::decode(&self.lightning_invoice)
+    }
+
+    /// Decode the lightning invoice and check it requests `sats_amount`
+    pub fn check_lightning_invoice(&self) -> Result<Bolt11Invoice, NodelessError> {
+        let decoded = self.decode_lightning_invoice()?;
+        decoded.check_amount(self.sats_amount)?;
+        Ok(decoded)
+    }
+}
+
+/// Split the human readable part into network and amount
+fn parse_hrp(hrp: &str) -> Result<(Network, Option<Msats>), NodelessError> {
+    let hrp = hrp
+        .strip_prefix("ln")
+        .ok_or_else(|| invalid("not a lightning invoice"))?;
+    let split = hrp.find(|c: char| c.is_ascii_digit()).unwrap_or(hrp.len());
+    let (prefix, amount) = hrp.split_at(split);
+
+    let network = match prefix {
+        "bc" => Network::Bitcoin,
+        "tb" => Network::Testnet,
+        "tbs" => Network::Signet,
+        "bcrt" => Network::Regtest,
+        _ => return Err(invalid(&format!("unknown network prefix {prefix}"))),
+    };
+
+    if amount.is_empty() {
+        return Ok((network, None));
+    }
+
+    let (digits, multiplier) = match amount.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits.parse().map_err(|_| invalid("invalid amount"))?;
+
+    let msats = match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        Some('p') if value % 10 == 0 => Some(value / 10),
+        Some('p') => return Err(invalid("amount has sub-millisatoshi precision")),
+        Some(c) => return Err(invalid(&format!("unknown amount multiplier {c}"))),
+    }
+    .ok_or_else(|| invalid("amount overflows"))?;
+
+    Ok((network, Some(Msats(msats))))
+}
+
+fn words_to_u64(words: &[u5]) -> u64 {
+    words
+        .iter()
+        .fold(0u64, |acc, word| (acc << 5) | u64::from(word.to_u8()))
+}
+
+fn words_to_bytes(words: &[u5]) -> Result<Vec<u8>, NodelessError> {
+    Vec::<u8>::from_base32(words).map_err(|err| invalid(&err.to_string()))
+}
+
+fn invalid(reason: &str) -> NodelessError {
+    NodelessError::InvalidBolt11(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Payment hash and secret shared by the BOLT11 test vectors
+    const PAYMENT_HASH: &str = "0001020304050607080900010203040506070809000102030405060708090102";
+    const PAYMENT_SECRET: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const TIMESTAMP: i64 = 1496314658;
+
+    #[test]
+    fn decodes_spec_donation() {
+        let invoice = Bolt11Invoice::decode("lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyql").unwrap();
+
+        assert_eq!(invoice.network, Network::Bitcoin);
+        assert_eq!(invoice.amount, None);
+        assert_eq!(invoice.timestamp, TIMESTAMP);
+        assert_eq!(invoice.payment_hash, PAYMENT_HASH);
+        assert_eq!(invoice.payment_secret.as_deref(), Some(PAYMENT_SECRET));
+        assert_eq!(
+            invoice.description,
+            Some(Bolt11Description::Direct(
+                "Please consider supporting this project".to_string()
+            ))
+        );
+        assert_eq!(invoice.expiry, DEFAULT_EXPIRY);
+        assert_eq!(invoice.expires_at(), TIMESTAMP + 3600);
+        assert_eq!(invoice.min_final_cltv_expiry, DEFAULT_MIN_FINAL_CLTV_EXPIRY);
+        assert_eq!(invoice.payee, None);
+    }
+
+    #[test]
+    fn decodes_spec_amount_and_expiry() {
+        let invoice = "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+        // Uppercase, as in qr codes, with the uri prefix
+        let uri = format!("LIGHTNING:{}", invoice.to_uppercase());
+        assert_eq!(
+            Bolt11Invoice::decode(&uri).unwrap(),
+            Bolt11Invoice::decode(invoice).unwrap()
+        );
+        let invoice = Bolt11Invoice::decode(invoice).unwrap();
+
+        assert_eq!(invoice.amount, Some(Msats(250_000_000)));
+        assert!(invoice.check_amount(Sats(250_000)).is_ok());
+        assert!(matches!(
+            invoice.check_amount(Sats(250_001)),
+            Err(NodelessError::AmountMismatch { .. })
+        ));
+        assert_eq!(invoice.timestamp, TIMESTAMP);
+        assert_eq!(invoice.payment_hash, PAYMENT_HASH);
+        assert_eq!(invoice.expiry, 60);
+        assert_eq!(invoice.expires_at(), TIMESTAMP + 60);
+    }
+
+    #[test]
+    fn decodes_spec_utf8_description() {
+        let invoice = Bolt11Invoice::decode("lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpquwpc4curk03c9wlrswe78q4eyqc7d8d0xqzpu9qrsgqhtjpauu9ur7fw2thcl4y9vfvh4m9wlfyz2gem29g5ghe2aak2pm3ps8fdhtceqsaagty2vph7utlgj48u0ged6a337aewvraedendscp573dxr").unwrap();
+
+        assert_eq!(
+            invoice.description,
+            Some(Bolt11Description::Direct("ナンセンス 1杯".to_string()))
+        );
+        assert_eq!(invoice.expiry, 60);
+    }
+
+    #[test]
+    fn decodes_spec_description_hash() {
+        let invoice = Bolt11Invoice::decode("lnbc20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqhp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqs9qrsgq7ea976txfraylvgzuxs8kgcw23ezlrszfnh8r6qtfpr6cxga50aj6txm9rxrydzd06dfeawfk6swupvz4erwnyutnjq7x39ymw6j38gp7ynn44").unwrap();
+
+        assert_eq!(invoice.amount, Some(Msats(2_000_000_000)));
+        assert_eq!(invoice.timestamp, TIMESTAMP);
+        assert_eq!(invoice.payment_hash, PAYMENT_HASH);
+        assert_eq!(
+            invoice.description,
+            Some(Bolt11Description::Hash(
+                "3925b6f67e2c340036ed12093dd44e0368df1b6ea26c53dbe4811f58fd5db8c1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_invoice() {
+        // Last character of the donation vector changed, the checksum fails
+        assert!(Bolt11Invoice::decode("lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyqm").is_err());
+        assert!(Bolt11Invoice::decode("lnbc1qqqqqqq").is_err());
+    }
+
+    fn amount(hrp: &str) -> Option<Msats> {
+        parse_hrp(hrp).unwrap().1
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(parse_hrp("lnbc").unwrap(), (Network::Bitcoin, None));
+        assert_eq!(parse_hrp("lntb").unwrap(), (Network::Testnet, None));
+        assert_eq!(parse_hrp("lntbs").unwrap(), (Network::Signet, None));
+        assert_eq!(parse_hrp("lnbcrt").unwrap(), (Network::Regtest, None));
+    }
+
+    #[test]
+    fn parses_multipliers() {
+        assert_eq!(amount("lnbc2"), Some(Msats(200_000_000_000)));
+        assert_eq!(amount("lnbc2m"), Some(Msats(200_000_000)));
+        assert_eq!(amount("lnbc2500u"), Some(Msats(250_000_000)));
+        assert_eq!(amount("lnbc20n"), Some(Msats(2_000)));
+        assert_eq!(amount("lnbc10p"), Some(Msats(1)));
+        assert_eq!(amount("lnbcrt1500n"), Some(Msats(150_000)));
+    }
+
+    #[test]
+    fn rejects_sub_msat_amounts() {
+        assert!(parse_hrp("lnbc1p").is_err());
+        assert!(parse_hrp("lnbc15p").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_hrp() {
+        assert!(parse_hrp("bc1").is_err());
+        assert!(parse_hrp("lnxy").is_err());
+        assert!(parse_hrp("lnltc20m").is_err());
+        assert!(parse_hrp("lnbc20x").is_err());
+        assert!(parse_hrp("lnbcm").is_err());
+        assert!(parse_hrp("lnbc99999999999999999999").is_err());
+        // Fits u64 as number, overflows as msats
+        assert!(parse_hrp("lnbc1000000000").is_err());
+    }
+}
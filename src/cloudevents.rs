@@ -0,0 +1,207 @@
+//! CloudEvents
+//!
+//! Converts verified webhook deliveries and status changes detected by the
+//! [`InvoiceReconciler`](crate::invoice_reconciler::InvoiceReconciler) into
+//! [CloudEvents 1.0](https://github.com/cloudevents/spec) with the structured
+//! and binary HTTP encodings.
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::error::NodelessError;
+use crate::invoice_reconciler::TrackedInvoice;
+use crate::webhook::{WebHookType, WebhookDelivery, WebhookEvent, WebhookTarget};
+use crate::Nodeless;
+
+/// CloudEvents specification version
+pub const SPEC_VERSION: &str = "1.0";
+
+/// Content type of the structured HTTP encoding
+pub const STRUCTURED_CONTENT_TYPE: &str = "application/cloudevents+json";
+
+/// CloudEvent
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datacontenttype: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Headers and body of an HTTP request carrying a [`CloudEvent`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpMessage {
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl CloudEvent {
+    /// Convert a delivery of `target` into a CloudEvent
+    ///
+    /// The `type` is `io.nodeless.<webhook type>.<resource>.<event>`, for
+    /// example `io.nodeless.store.invoice.paid`. The id is derived from the
+    /// webhook type, invoice id and event, so redeliveries and status changes
+    /// detected by polling map to the same id.
+    /// # Arguments
+    /// * `api_url` - Url of the nodeless api the `source` is derived from
+    /// * `target` - Store or paywall the delivery belongs to
+    /// * `delivery` - Verified delivery
+    pub fn from_delivery(
+        api_url: &Url,
+        target: &WebhookTarget,
+        delivery: &WebhookDelivery,
+    ) -> Self {
+        let type_ = delivery.type_.as_str();
+        let event = delivery.event().as_str();
+        let uuid = &delivery.payload.uuid;
+
+        let paid_at = match delivery.payload.paid_at {
+            Some(at) if is_payment(delivery.event()) => DateTime::<Utc>::from_timestamp(at, 0),
+            _ => None,
+        };
+        let time = paid_at.unwrap_or_else(Utc::now);
+
+        Self {
+            specversion: SPEC_VERSION.to_string(),
+            id: format!("{type_}:{uuid}:{event}"),
+            source: source(api_url, target),
+            type_: format!(
+                "io.nodeless.{}.{}.{}",
+                type_,
+                resource_name(&delivery.type_),
+                event
+            ),
+            subject: Some(uuid.clone()),
+            time: Some(time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            datacontenttype: Some("application/json".to_string()),
+            data: Some(delivery.raw.clone()),
+        }
+    }
+
+    /// Convert a status change of `invoice` detected by polling into a
+    /// CloudEvent
+    pub fn from_tracked(
+        api_url: &Url,
+        invoice: &TrackedInvoice,
+        delivery: &WebhookDelivery,
+    ) -> Self {
+        Self::from_delivery(api_url, &invoice.target(), delivery)
+    }
+
+    /// Structured HTTP encoding, the whole event as json body
+    pub fn to_structured(&self) -> Result<HttpMessage, NodelessError> {
+        Ok(HttpMessage {
+            headers: vec![(
+                "Content-Type".to_string(),
+                STRUCTURED_CONTENT_TYPE.to_string(),
+            )],
+            body: serde_json::to_vec(self)?,
+        })
+    }
+
+    /// Binary HTTP encoding, attributes as `ce-` headers and data as body
+    pub fn to_binary(&self) -> Result<HttpMessage, NodelessError> {
+        let mut headers = vec![
+            ("ce-specversion".to_string(), self.specversion.clone()),
+            ("ce-id".to_string(), self.id.clone()),
+            ("ce-source".to_string(), self.source.clone()),
+            ("ce-type".to_string(), self.type_.clone()),
+        ];
+        if let Some(subject) = &self.subject {
+            headers.push(("ce-subject".to_string(), subject.clone()));
+        }
+        if let Some(time) = &self.time {
+            headers.push(("ce-time".to_string(), time.clone()));
+        }
+        if let Some(content_type) = &self.datacontenttype {
+            headers.push(("Content-Type".to_string(), content_type.clone()));
+        }
+
+        let body = match &self.data {
+            Some(data) => serde_json::to_vec(data)?,
+            None => Vec::new(),
+        };
+        Ok(HttpMessage { headers, body })
+    }
+}
+
+impl Nodeless {
+    /// Convert a delivery of `target` into a CloudEvent sourced from the api
+    /// url of the client
+    pub fn cloud_event(&self, target: &WebhookTarget, delivery: &WebhookDelivery) -> CloudEvent {
+        CloudEvent::from_delivery(&self.base_url, target, delivery)
+    }
+}
+
+/// Source of events of `target`
+fn source(api_url: &Url, target: &WebhookTarget) -> String {
+    let api_url = api_url.as_str().trim_end_matches('/');
+    match target {
+        WebhookTarget::Store(id) => format!("{api_url}/api/v1/store/{id}"),
+        WebhookTarget::Paywall(id) => format!("{api_url}/api/v1/paywall/{id}"),
+    }
+}
+
+/// Name of the resource a webhook type reports on
+fn resource_name(type_: &WebHookType) -> &'static str {
+    match type_ {
+        WebHookType::Store => "invoice",
+        WebHookType::Paywall => "request",
+        WebHookType::DonationPage => "donation",
+        WebHookType::Inbox => "payment",
+        WebHookType::Unknown(_) => "event",
+    }
+}
+
+fn is_payment(event: &WebhookEvent) -> bool {
+    matches!(
+        event,
+        WebhookEvent::Paid | WebhookEvent::Overpaid | WebhookEvent::Underpaid
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn delivery() -> WebhookDelivery {
+        WebhookDelivery::from_raw(
+            WebHookType::Store,
+            json!({"uuid": "invoice-id", "status": "paid"}),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn source_is_derived_from_api_url() {
+        let target = WebhookTarget::Store("store-id".to_string());
+
+        let client =
+            Nodeless::new("api-key", Some("https://testnet.nodeless.io".to_string())).unwrap();
+        let event = client.cloud_event(&target, &delivery());
+        assert_eq!(
+            event.source,
+            "https://testnet.nodeless.io/api/v1/store/store-id"
+        );
+        assert_eq!(event.id, "store:invoice-id:paid");
+        assert_eq!(event.type_, "io.nodeless.store.invoice.paid");
+
+        let api_url = Url::from_str("http://localhost:8080/").unwrap();
+        let event = CloudEvent::from_delivery(&api_url, &target, &delivery());
+        assert_eq!(event.source, "http://localhost:8080/api/v1/store/store-id");
+    }
+}
@@ -0,0 +1,259 @@
+//! Currency
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::RoundingStrategy;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::amount::FiatAmount;
+use crate::error::NodelessError;
+
+/// Currency of an invoice request
+///
+/// Parsing with [`FromStr`] only accepts known currencies, so typos fail
+/// before reaching the api. Deserializing falls back to
+/// [`Currency::Other`], which keeps the code verbatim.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Currency {
+    USD,
+    EUR,
+    GBP,
+    CAD,
+    AUD,
+    NZD,
+    CHF,
+    JPY,
+    CNY,
+    HKD,
+    SGD,
+    KRW,
+    INR,
+    BRL,
+    MXN,
+    ARS,
+    ZAR,
+    SEK,
+    NOK,
+    DKK,
+    PLN,
+    CZK,
+    HUF,
+    TRY,
+    BTC,
+    SATS,
+    Other(String),
+}
+
+impl Currency {
+    /// Every known currency
+    pub fn all() -> Vec<Currency> {
+        use Currency::*;
+
+        vec![
+            USD, EUR, GBP, CAD, AUD, NZD, CHF, JPY, CNY, HKD, SGD, KRW, INR, BRL, MXN, ARS, ZAR,
+            SEK, NOK, DKK, PLN, CZK, HUF, TRY, BTC, SATS,
+        ]
+    }
+
+    /// Code of the currency as used by the api
+    pub fn as_str(&self) -> &str {
+        use Currency::*;
+
+        match self {
+            USD => "USD",
+            EUR => "EUR",
+            GBP => "GBP",
+            CAD => "CAD",
+            AUD => "AUD",
+            NZD => "NZD",
+            CHF => "CHF",
+            JPY => "JPY",
+            CNY => "CNY",
+            HKD => "HKD",
+            SGD => "SGD",
+            KRW => "KRW",
+            INR => "INR",
+            BRL => "BRL",
+            MXN => "MXN",
+            ARS => "ARS",
+            ZAR => "ZAR",
+            SEK => "SEK",
+            NOK => "NOK",
+            DKK => "DKK",
+            PLN => "PLN",
+            CZK => "CZK",
+            HUF => "HUF",
+            TRY => "TRY",
+            BTC => "BTC",
+            SATS => "SATS",
+            Other(code) => code,
+        }
+    }
+
+    /// Number of decimal places of the minor unit, `None` for
+    /// [`Currency::Other`]
+    pub fn precision(&self) -> Option<u32> {
+        match self {
+            Currency::JPY | Currency::KRW | Currency::SATS => Some(0),
+            Currency::BTC => Some(8),
+            Currency::Other(_) => None,
+            _ => Some(2),
+        }
+    }
+
+    /// Round `amount` half away from zero to the minor unit
+    ///
+    /// Requests are never rounded implicitly, round amounts with more decimal
+    /// places than the currency before building one. Amounts in
+    /// [`Currency::Other`] are returned unchanged.
+    pub fn round(&self, amount: FiatAmount) -> FiatAmount {
+        match self.precision() {
+            Some(precision) => FiatAmount::new(
+                amount
+                    .as_decimal()
+                    .round_dp_with_strategy(precision, RoundingStrategy::MidpointAwayFromZero),
+            )
+            .expect("rounding keeps the sign"),
+            None => amount,
+        }
+    }
+
+    /// Check `amount` is not zero and has no more decimal places than the
+    /// minor unit
+    ///
+    /// Trailing zeros do not count, `21.210` is a valid [`Currency::USD`]
+    /// amount.
+    pub fn validate_amount(&self, amount: FiatAmount) -> Result<(), NodelessError> {
+        if amount.as_decimal().is_zero() {
+            return Err(NodelessError::InvalidAmount(
+                "amount must be positive".to_string(),
+            ));
+        }
+        if let Some(precision) = self.precision() {
+            if amount.as_decimal().normalize().scale() > precision {
+                return Err(NodelessError::InvalidAmount(format!(
+                    "{self} amounts have at most {precision} decimal places, found {amount}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = NodelessError;
+
+    /// Parse a known currency code, ignoring case and surrounding whitespace
+    ///
+    /// `"usd"` parses as [`Currency::USD`], which serializes as `"USD"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim().to_uppercase();
+        Currency::all()
+            .into_iter()
+            .find(|currency| currency.as_str() == code)
+            .ok_or_else(|| NodelessError::UnsupportedCurrency(s.to_string()))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = String::deserialize(deserializer)?;
+        Ok(Currency::all()
+            .into_iter()
+            .find(|currency| currency.as_str() == code)
+            .unwrap_or(Currency::Other(code)))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(s: &str) -> FiatAmount {
+        FiatAmount::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn rounds_to_minor_unit() {
+        for (currency, input, rounded) in [
+            (Currency::USD, "21.215", "21.22"),
+            (Currency::USD, "21.214", "21.21"),
+            (Currency::EUR, "0.005", "0.01"),
+            (Currency::JPY, "2100.5", "2101"),
+            (Currency::KRW, "2100.4", "2100"),
+            (Currency::SATS, "0.5", "1"),
+            (Currency::BTC, "0.000000015", "0.00000002"),
+            (Currency::Other("XAU".to_string()), "1.23456", "1.23456"),
+        ] {
+            assert_eq!(
+                currency.round(amount(input)),
+                amount(rounded),
+                "{input} {currency}"
+            );
+        }
+    }
+
+    #[test]
+    fn validates_precision_without_rounding() {
+        assert!(Currency::USD.validate_amount(amount("21.21")).is_ok());
+        assert!(Currency::USD.validate_amount(amount("21.210")).is_ok());
+        assert!(Currency::USD.validate_amount(amount("21.215")).is_err());
+        assert!(Currency::USD.validate_amount(amount("0.00")).is_err());
+        assert!(Currency::JPY.validate_amount(amount("2100.5")).is_err());
+        assert!(Currency::BTC.validate_amount(amount("0.00000001")).is_ok());
+        assert!(Currency::Other("XAU".to_string())
+            .validate_amount(amount("1.23456"))
+            .is_ok());
+    }
+
+    #[test]
+    fn parses_known_codes_in_any_case() {
+        for currency in Currency::all() {
+            assert_eq!(Currency::from_str(currency.as_str()).unwrap(), currency);
+            assert_eq!(
+                Currency::from_str(&currency.as_str().to_lowercase()).unwrap(),
+                currency
+            );
+        }
+        assert_eq!(Currency::from_str(" eur ").unwrap(), Currency::EUR);
+        assert!(matches!(
+            Currency::from_str("usdd"),
+            Err(NodelessError::UnsupportedCurrency(code)) if code == "usdd"
+        ));
+    }
+
+    #[test]
+    fn serde_round_trips() {
+        for currency in Currency::all() {
+            let json = format!("\"{}\"", currency.as_str());
+            assert_eq!(serde_json::to_string(&currency).unwrap(), json);
+            assert_eq!(serde_json::from_str::<Currency>(&json).unwrap(), currency);
+        }
+
+        // Codes the crate does not know, or spells differently, are kept verbatim
+        for code in ["XAU", "usd"] {
+            let json = format!("\"{code}\"");
+            let currency: Currency = serde_json::from_str(&json).unwrap();
+            assert_eq!(currency, Currency::Other(code.to_string()));
+            assert_eq!(serde_json::to_string(&currency).unwrap(), json);
+        }
+    }
+}
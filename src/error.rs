@@ -9,4 +9,61 @@ pub enum NodelessError {
     SerdeError(#[from] serde_json::Error),
     #[error("Invalid Response")]
     InvalidResponse,
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+    #[error("QR code error: {0}")]
+    QrCodeError(String),
+    #[error("Invalid BIP21 uri: {0}")]
+    InvalidBip21(String),
+    #[error("Invalid BOLT11 invoice: {0}")]
+    InvalidBolt11(String),
+    #[error("Amount mismatch: expected {expected}, found {found}")]
+    AmountMismatch {
+        expected: crate::amount::Msats,
+        found: crate::amount::Msats,
+    },
+    #[error("Invalid invoice: {0}")]
+    InvalidInvoice(String),
+    #[error("Unsupported currency: {0}")]
+    UnsupportedCurrency(String),
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(String),
+    #[error("Unknown status: {0}")]
+    UnknownStatus(String),
+    #[error("Timeout")]
+    Timeout,
+    #[error("Invoice not paid: {}", .0.as_str())]
+    InvoiceNotPaid(crate::store::InvoiceStatus),
+    #[error("seen store full: {0} unexpired keys")]
+    SeenStoreFull(usize),
+    #[error("Invalid Signature")]
+    InvalidSignature,
+    #[error("invalid webhook: {0}")]
+    InvalidWebhook(String),
+    #[error("handler error: {0}")]
+    HandlerError(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl NodelessError {
+    /// Check if the failed request may succeed when retried
+    ///
+    /// Connection failures, timeouts, bodies that are not json (such as the
+    /// error page of a proxy), server errors and rate limits are retryable.
+    /// Other client errors and json of an unexpected shape, as returned for an
+    /// invalid api key or an unknown id, are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            NodelessError::ReqwestError(err) => match err.status() {
+                Some(status) => {
+                    status.is_server_error()
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                None => err.is_connect() || err.is_timeout() || err.is_request() || err.is_decode(),
+            },
+            NodelessError::InvalidResponse | NodelessError::Timeout => true,
+            _ => false,
+        }
+    }
 }
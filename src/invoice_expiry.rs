@@ -0,0 +1,339 @@
+//! Invoice Expiry
+//!
+//! An invoice expires the expiry of its lightning invoice after it was
+//! created. Without the `bolt11` feature, or if the lightning invoice cannot
+//! be decoded, the expiry of the store is used.
+//!
+//! # Example
+//! ```
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! use nodeless_rs::invoice_expiry::{ExpiryPolicy, ManualClock};
+//! use nodeless_rs::store::Invoice;
+//!
+//! fn countdown(invoice: &Invoice) {
+//!     let clock = Arc::new(ManualClock::new(invoice.created_at));
+//!     let policy = ExpiryPolicy::new()
+//!         .with_store_expiry(&invoice.store.id, Duration::from_secs(15 * 60))
+//!         .with_clock(clock.clone());
+//!
+//!     println!("{:?} left", policy.time_remaining(invoice));
+//!     clock.advance(Duration::from_secs(60));
+//!     println!("{:?} left", policy.time_remaining(invoice));
+//! }
+//! ```
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::store::{Invoice, InvoiceStatus};
+
+/// Expiry used when neither the lightning invoice nor the store define one
+pub const DEFAULT_INVOICE_EXPIRY: Duration = Duration::from_secs(3600);
+
+/// Source of the current unix timestamp
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// System time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+}
+
+/// Clock that only moves when told to, for tests
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    /// Set the current timestamp
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        self.now
+            .fetch_add(duration.as_secs() as i64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+impl<M> Invoice<M> {
+    /// Unix timestamp the invoice expires at
+    ///
+    /// Uses [`DEFAULT_INVOICE_EXPIRY`] if the lightning invoice does not tell.
+    pub fn expires_at(&self) -> i64 {
+        self.expires_at_or(DEFAULT_INVOICE_EXPIRY)
+    }
+
+    /// Unix timestamp the invoice expires at, using `default_expiry` if the
+    /// lightning invoice does not tell
+    pub fn expires_at_or(&self, default_expiry: Duration) -> i64 {
+        let expiry = lightning_expiry(self).unwrap_or(default_expiry);
+        self.created_at
+            .saturating_add(i64::try_from(expiry.as_secs()).unwrap_or(i64::MAX))
+    }
+
+    /// Time left until the invoice expires, zero once expired
+    pub fn time_remaining(&self, now: i64) -> Duration {
+        remaining(self.expires_at(), now)
+    }
+
+    /// The invoice expired, by status or by time
+    ///
+    /// Settled invoices never expire.
+    pub fn is_expired(&self, now: i64) -> bool {
+        expired(&self.status, now, self.expires_at())
+    }
+}
+
+/// Expiry Policy
+///
+/// Computes the expiry of invoices with per store defaults and an
+/// injectable [`Clock`].
+#[derive(Clone)]
+pub struct ExpiryPolicy {
+    default_expiry: Duration,
+    store_expiry: HashMap<String, Duration>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExpiryPolicy {
+    /// Create policy using [`DEFAULT_INVOICE_EXPIRY`] and the system time
+    pub fn new() -> Self {
+        Self {
+            default_expiry: DEFAULT_INVOICE_EXPIRY,
+            store_expiry: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Set expiry of stores without their own
+    pub fn with_default_expiry(mut self, expiry: Duration) -> Self {
+        self.default_expiry = expiry;
+        self
+    }
+
+    /// Set expiry of invoices of `store_id`
+    pub fn with_store_expiry(mut self, store_id: &str, expiry: Duration) -> Self {
+        self.store_expiry.insert(store_id.to_string(), expiry);
+        self
+    }
+
+    /// Set clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Unix timestamp `invoice` expires at
+    pub fn expires_at<M>(&self, invoice: &Invoice<M>) -> i64 {
+        let default_expiry = self
+            .store_expiry
+            .get(&invoice.store.id)
+            .copied()
+            .unwrap_or(self.default_expiry);
+        invoice.expires_at_or(default_expiry)
+    }
+
+    /// Time left until `invoice` expires, zero once expired
+    pub fn time_remaining<M>(&self, invoice: &Invoice<M>) -> Duration {
+        remaining(self.expires_at(invoice), self.clock.now())
+    }
+
+    /// `invoice` expired, by status or by time
+    ///
+    /// Settled invoices never expire.
+    pub fn is_expired<M>(&self, invoice: &Invoice<M>) -> bool {
+        expired(&invoice.status, self.clock.now(), self.expires_at(invoice))
+    }
+}
+
+fn expired(status: &InvoiceStatus, now: i64, expires_at: i64) -> bool {
+    !status.is_settled() && (*status == InvoiceStatus::Expired || now >= expires_at)
+}
+
+fn remaining(expires_at: i64, now: i64) -> Duration {
+    Duration::from_secs(expires_at.saturating_sub(now).max(0) as u64)
+}
+
+#[cfg(feature = "bolt11")]
+fn lightning_expiry<M>(invoice: &Invoice<M>) -> Option<Duration> {
+    invoice
+        .decode_lightning_invoice()
+        .ok()
+        .map(|decoded| Duration::from_secs(decoded.expiry))
+}
+
+#[cfg(not(feature = "bolt11"))]
+fn lightning_expiry<M>(_invoice: &Invoice<M>) -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Not decodable, so the expiry falls back to the store or the default
+    const UNDECODABLE: &str = "lnbc210u1pinvoice";
+
+    fn invoice(status: &str, lightning_invoice: &str) -> Invoice {
+        serde_json::from_value(json!({
+            "id": "invoice-id",
+            "checkoutLink": null,
+            "satsAmount": 21000,
+            "status": status,
+            "buyerEmail": "hi@nodeless.io",
+            "redirectUrl": "https://nodeless.io",
+            "metadata": [],
+            "createdAt": "2023-06-01T12:00:00.000000Z",
+            "paidAt": null,
+            "onchainAddress": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "lightningInvoice": lightning_invoice,
+            "store": {
+                "id": "store-id",
+                "name": "Store",
+                "url": null,
+                "email": null,
+                "createdAt": "2023-06-01T12:00:00.000000Z"
+            },
+            "qrCodes": {
+                "unified": "",
+                "onchain": "",
+                "lightning": ""
+            }
+        }))
+        .unwrap()
+    }
+
+    fn policy(now: i64) -> ExpiryPolicy {
+        ExpiryPolicy::new().with_clock(Arc::new(ManualClock::new(now)))
+    }
+
+    #[test]
+    fn falls_back_to_store_then_default_expiry() {
+        let invoice = invoice("new", UNDECODABLE);
+        let created_at = invoice.created_at;
+
+        assert_eq!(invoice.expires_at(), created_at + 3600);
+        assert_eq!(policy(0).expires_at(&invoice), created_at + 3600);
+        assert_eq!(
+            policy(0)
+                .with_default_expiry(Duration::from_secs(600))
+                .expires_at(&invoice),
+            created_at + 600
+        );
+        assert_eq!(
+            policy(0)
+                .with_default_expiry(Duration::from_secs(600))
+                .with_store_expiry("store-id", Duration::from_secs(900))
+                .expires_at(&invoice),
+            created_at + 900
+        );
+        assert_eq!(
+            policy(0)
+                .with_store_expiry("other-store", Duration::from_secs(900))
+                .expires_at(&invoice),
+            created_at + 3600
+        );
+    }
+
+    #[cfg(feature = "bolt11")]
+    #[test]
+    fn lightning_expiry_takes_precedence() {
+        // BOLT11 test vector expiring after 60 seconds
+        let invoice = invoice("new", "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh");
+        let created_at = invoice.created_at;
+
+        assert_eq!(invoice.expires_at(), created_at + 60);
+        assert_eq!(
+            policy(0)
+                .with_store_expiry("store-id", Duration::from_secs(900))
+                .expires_at(&invoice),
+            created_at + 60
+        );
+    }
+
+    #[test]
+    fn time_remaining_clamps_to_zero() {
+        let invoice = invoice("new", UNDECODABLE);
+        let created_at = invoice.created_at;
+
+        assert_eq!(
+            invoice.time_remaining(created_at),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            invoice.time_remaining(created_at + 3599),
+            Duration::from_secs(1)
+        );
+        assert_eq!(invoice.time_remaining(created_at + 3600), Duration::ZERO);
+        assert_eq!(invoice.time_remaining(i64::MAX), Duration::ZERO);
+
+        let clock = Arc::new(ManualClock::new(created_at));
+        let policy = ExpiryPolicy::new()
+            .with_store_expiry("store-id", Duration::from_secs(900))
+            .with_clock(clock.clone());
+        assert_eq!(policy.time_remaining(&invoice), Duration::from_secs(900));
+        clock.advance(Duration::from_secs(1000));
+        assert_eq!(policy.time_remaining(&invoice), Duration::ZERO);
+    }
+
+    #[test]
+    fn expires_by_time_or_status() {
+        let pending = invoice("new", UNDECODABLE);
+        let created_at = pending.created_at;
+        assert!(!pending.is_expired(created_at + 3599));
+        assert!(pending.is_expired(created_at + 3600));
+
+        let expired = invoice("expired", UNDECODABLE);
+        assert!(expired.is_expired(created_at));
+        assert!(policy(created_at).is_expired(&expired));
+
+        assert!(!policy(created_at).is_expired(&pending));
+        assert!(policy(created_at + 3600).is_expired(&pending));
+    }
+
+    #[test]
+    fn settled_invoices_never_expire() {
+        for status in ["paid", "overpaid"] {
+            let invoice = invoice(status, UNDECODABLE);
+            let late = invoice.created_at + 7200;
+            assert!(!invoice.is_expired(late), "{status}");
+            assert!(!policy(late).is_expired(&invoice), "{status}");
+        }
+
+        // An underpaid invoice still expires
+        let underpaid = invoice("underpaid", UNDECODABLE);
+        assert!(underpaid.is_expired(underpaid.created_at + 7200));
+    }
+}
@@ -0,0 +1,201 @@
+//! Invoice Lifecycle
+//!
+//! Webhooks and polling can report statuses out of order, for example a
+//! stale poll returning `new` after the `paid` webhook was handled.
+//! [`InvoiceLifecycle`] only applies legal transitions and keeps a
+//! timestamped history, so it can be stored with an order record and
+//! updated from any source.
+//!
+//! # Example
+//! ```
+//! use nodeless_rs::invoice_lifecycle::{InvoiceLifecycle, Transition};
+//! use nodeless_rs::store::InvoiceStatus;
+//!
+//! let mut lifecycle = InvoiceLifecycle::new("invoice-id", InvoiceStatus::New, 1_700_000_000);
+//!
+//! let transition = lifecycle.apply(InvoiceStatus::Paid, 1_700_000_060);
+//! assert!(matches!(transition, Transition::Applied(_)));
+//!
+//! let transition = lifecycle.apply(InvoiceStatus::New, 1_700_000_090);
+//! assert!(matches!(transition, Transition::Rejected { .. }));
+//! assert_eq!(lifecycle.status(), &InvoiceStatus::Paid);
+//! ```
+use serde::{Deserialize, Serialize};
+
+use crate::paywall::PaywallRequest;
+use crate::store::{Invoice, InvoiceStatus};
+use crate::webhook::WebhookDelivery;
+
+/// Applied status change
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusChange {
+    pub from: Option<InvoiceStatus>,
+    pub to: InvoiceStatus,
+    /// Unix timestamp the change was observed at
+    pub at: i64,
+}
+
+/// Result of applying a status
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// Status changed and was added to the history
+    Applied(StatusChange),
+    /// Status is already the current one
+    Unchanged,
+    /// Status cannot follow the current one and was ignored
+    Rejected {
+        from: InvoiceStatus,
+        to: InvoiceStatus,
+    },
+}
+
+/// Lifecycle of a store invoice or paywall request
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceLifecycle {
+    id: String,
+    status: InvoiceStatus,
+    history: Vec<StatusChange>,
+}
+
+impl InvoiceLifecycle {
+    /// Start the lifecycle of invoice `id` in `status`
+    /// # Arguments
+    /// * `id` - Id of the invoice or paywall request
+    /// * `status` - Initial status
+    /// * `at` - Unix timestamp the status was observed at
+    pub fn new(id: &str, status: InvoiceStatus, at: i64) -> Self {
+        Self {
+            id: id.to_string(),
+            history: vec![StatusChange {
+                from: None,
+                to: status.clone(),
+                at,
+            }],
+            status,
+        }
+    }
+
+    /// Start the lifecycle of a fetched store invoice
+    pub fn from_invoice<M>(invoice: &Invoice<M>) -> Self {
+        Self::new(
+            invoice.id.as_deref().unwrap_or_default(),
+            invoice.status.clone(),
+            invoice.created_at,
+        )
+    }
+
+    /// Start the lifecycle of a fetched paywall request
+    pub fn from_paywall_request<M>(request: &PaywallRequest<M>) -> Self {
+        Self::new(
+            &request.id,
+            InvoiceStatus::from(request.status.as_str()),
+            request.created_at,
+        )
+    }
+
+    /// Id of the invoice or paywall request
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Current status
+    pub fn status(&self) -> &InvoiceStatus {
+        &self.status
+    }
+
+    /// Applied status changes, oldest first
+    pub fn history(&self) -> &[StatusChange] {
+        &self.history
+    }
+
+    /// Apply `status` observed at `at`
+    ///
+    /// Illegal transitions, such as a stale poll reporting `new` for a paid
+    /// invoice, leave the lifecycle unchanged and are returned as
+    /// [`Transition::Rejected`].
+    pub fn apply(&mut self, status: InvoiceStatus, at: i64) -> Transition {
+        if status == self.status {
+            return Transition::Unchanged;
+        }
+        if !self.status.can_transition_to(&status) {
+            return Transition::Rejected {
+                from: self.status.clone(),
+                to: status,
+            };
+        }
+
+        let change = StatusChange {
+            from: Some(self.status.clone()),
+            to: status.clone(),
+            at,
+        };
+        self.status = status;
+        self.history.push(change.clone());
+        Transition::Applied(change)
+    }
+
+    /// Apply the status of a webhook delivery received at `at`
+    ///
+    /// Deliveries for other invoices are rejected.
+    pub fn apply_delivery(&mut self, delivery: &WebhookDelivery, at: i64) -> Transition {
+        let status = InvoiceStatus::from(delivery.event().as_str());
+        if delivery.payload.uuid != self.id {
+            return Transition::Rejected {
+                from: self.status.clone(),
+                to: status,
+            };
+        }
+        self.apply(status, at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use InvoiceStatus::*;
+
+    fn unknown() -> InvoiceStatus {
+        Unknown("refunding".to_string())
+    }
+
+    #[test]
+    fn terminal_status_does_not_leave_through_unknown() {
+        let mut lifecycle = InvoiceLifecycle::new("invoice-id", New, 1);
+        assert!(matches!(lifecycle.apply(Paid, 2), Transition::Applied(_)));
+        assert!(matches!(
+            lifecycle.apply(unknown(), 3),
+            Transition::Rejected { .. }
+        ));
+        assert!(matches!(
+            lifecycle.apply(New, 4),
+            Transition::Rejected { .. }
+        ));
+        assert_eq!(lifecycle.status(), &Paid);
+        assert_eq!(lifecycle.history().len(), 2);
+    }
+
+    #[test]
+    fn late_payment_settles_expired_invoice() {
+        let mut lifecycle = InvoiceLifecycle::new("invoice-id", New, 1);
+        assert!(matches!(
+            lifecycle.apply(Expired, 2),
+            Transition::Applied(_)
+        ));
+        assert!(matches!(lifecycle.apply(Paid, 3), Transition::Applied(_)));
+        assert!(matches!(
+            lifecycle.apply(Expired, 4),
+            Transition::Rejected { .. }
+        ));
+        assert_eq!(lifecycle.status(), &Paid);
+        assert_eq!(lifecycle.history().len(), 3);
+    }
+
+    #[test]
+    fn unchanged_status_is_not_recorded() {
+        let mut lifecycle = InvoiceLifecycle::new("invoice-id", New, 1);
+        assert_eq!(lifecycle.apply(New, 2), Transition::Unchanged);
+        assert_eq!(lifecycle.history().len(), 1);
+    }
+}
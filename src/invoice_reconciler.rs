@@ -0,0 +1,471 @@
+//! Invoice Reconciler
+//!
+//! Webhooks can get lost, for example while the receiver is being deployed.
+//! [`InvoiceReconciler`] tracks store invoices and paywall requests that have
+//! not reached a final state, polls their status and dispatches a synthesized
+//! [`WebhookDelivery`] through the [`WebhookRouter`] for every status change
+//! that was not already received as a webhook.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde_json::Value;
+
+use crate::error::NodelessError;
+use crate::store::InvoiceStatus;
+use crate::webhook::{WebHookType, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookTarget};
+use crate::webhook_dedup::{DedupOutcome, WebhookDeduplicator};
+use crate::webhook_router::WebhookRouter;
+use crate::Nodeless;
+
+/// Invoice or paywall request tracked by the reconciler
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrackedInvoice {
+    StoreInvoice {
+        store_id: String,
+        invoice_id: String,
+    },
+    PaywallRequest {
+        paywall_id: String,
+        request_id: String,
+    },
+}
+
+impl TrackedInvoice {
+    /// Type of the webhooks sent for the invoice
+    pub fn webhook_type(&self) -> WebHookType {
+        match self {
+            TrackedInvoice::StoreInvoice { .. } => WebHookType::Store,
+            TrackedInvoice::PaywallRequest { .. } => WebHookType::Paywall,
+        }
+    }
+
+    /// Store or paywall the invoice belongs to
+    pub fn target(&self) -> WebhookTarget {
+        match self {
+            TrackedInvoice::StoreInvoice { store_id, .. } => WebhookTarget::Store(store_id.clone()),
+            TrackedInvoice::PaywallRequest { paywall_id, .. } => {
+                WebhookTarget::Paywall(paywall_id.clone())
+            }
+        }
+    }
+
+    /// Id of the invoice or request, the `uuid` of its webhook payloads
+    pub fn id(&self) -> &str {
+        match self {
+            TrackedInvoice::StoreInvoice { invoice_id, .. } => invoice_id,
+            TrackedInvoice::PaywallRequest { request_id, .. } => request_id,
+        }
+    }
+}
+
+/// Source of the current status of tracked invoices
+///
+/// Implemented by [`Nodeless`], which fetches the status from the api.
+pub trait StatusSource: Send + Sync {
+    /// Current status of `invoice`
+    fn status<'a>(
+        &'a self,
+        invoice: &'a TrackedInvoice,
+    ) -> BoxFuture<'a, Result<WebhookEvent, NodelessError>>;
+}
+
+impl StatusSource for Nodeless {
+    fn status<'a>(
+        &'a self,
+        invoice: &'a TrackedInvoice,
+    ) -> BoxFuture<'a, Result<WebhookEvent, NodelessError>> {
+        Box::pin(async move {
+            match invoice {
+                TrackedInvoice::StoreInvoice {
+                    store_id,
+                    invoice_id,
+                } => {
+                    let status = self.get_store_invoice_status(store_id, invoice_id).await?;
+                    Ok(WebhookEvent::from(status))
+                }
+                TrackedInvoice::PaywallRequest {
+                    paywall_id,
+                    request_id,
+                } => {
+                    let status = self
+                        .get_paywall_request_status(paywall_id, request_id)
+                        .await?;
+                    Ok(WebhookEvent::from(status.as_str()))
+                }
+            }
+        })
+    }
+}
+
+/// Result of polling the tracked invoices
+#[derive(Debug, Default)]
+pub struct PollSummary {
+    /// Synthesized deliveries that were dispatched
+    pub dispatched: Vec<WebhookDelivery>,
+    /// Invoices whose status could not be fetched or whose delivery failed
+    pub errors: Vec<(TrackedInvoice, NodelessError)>,
+}
+
+/// Invoice Reconciler
+pub struct InvoiceReconciler {
+    source: Arc<dyn StatusSource>,
+    router: WebhookRouter,
+    deduplicator: Option<WebhookDeduplicator>,
+    tracked: Mutex<HashMap<TrackedInvoice, WebhookEvent>>,
+}
+
+impl InvoiceReconciler {
+    /// Create reconciler dispatching synthesized events with `router`
+    pub fn new(client: Nodeless, router: WebhookRouter) -> Self {
+        Self::with_status_source(Arc::new(client), router)
+    }
+
+    /// Create reconciler polling the status from `source`
+    pub fn with_status_source(source: Arc<dyn StatusSource>, router: WebhookRouter) -> Self {
+        Self {
+            source,
+            router,
+            deduplicator: None,
+            tracked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Skip events already seen by `deduplicator`
+    ///
+    /// Share the deduplicator with the webhook receiver so an event is handled
+    /// once, whether it arrives by webhook or by polling.
+    pub fn with_deduplicator(mut self, deduplicator: WebhookDeduplicator) -> Self {
+        self.deduplicator = Some(deduplicator);
+        self
+    }
+
+    /// Track a newly created store invoice
+    pub fn track_store_invoice(&self, store_id: &str, invoice_id: &str) {
+        self.track(
+            TrackedInvoice::StoreInvoice {
+                store_id: store_id.to_string(),
+                invoice_id: invoice_id.to_string(),
+            },
+            WebhookEvent::New,
+        );
+    }
+
+    /// Track a newly created paywall request
+    pub fn track_paywall_request(&self, paywall_id: &str, request_id: &str) {
+        self.track(
+            TrackedInvoice::PaywallRequest {
+                paywall_id: paywall_id.to_string(),
+                request_id: request_id.to_string(),
+            },
+            WebhookEvent::New,
+        );
+    }
+
+    /// Track `invoice` with its last known status
+    pub fn track(&self, invoice: TrackedInvoice, status: WebhookEvent) {
+        self.tracked
+            .lock()
+            .expect("reconciler lock poisoned")
+            .insert(invoice, status);
+    }
+
+    /// Record a delivery received by webhook
+    ///
+    /// Updates the last known status of the matching invoice so polling does
+    /// not synthesize the event again. Invoices in a final state are no longer
+    /// tracked.
+    pub fn observe(&self, delivery: &WebhookDelivery) {
+        let mut tracked = self.tracked.lock().expect("reconciler lock poisoned");
+        let key = tracked
+            .keys()
+            .find(|invoice| {
+                invoice.webhook_type() == delivery.type_ && invoice.id() == delivery.payload.uuid
+            })
+            .cloned();
+
+        if let Some(key) = key {
+            if is_final(delivery.event()) {
+                tracked.remove(&key);
+            } else {
+                tracked.insert(key, delivery.event().clone());
+            }
+        }
+    }
+
+    /// Invoices not in a final state yet
+    pub fn pending(&self) -> Vec<TrackedInvoice> {
+        self.tracked
+            .lock()
+            .expect("reconciler lock poisoned")
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Poll every tracked invoice once
+    ///
+    /// An invoice whose status cannot be fetched or whose handler fails is
+    /// recorded in [`PollSummary::errors`] and the remaining invoices are still
+    /// polled. Its delivery is synthesized again on the next poll, as is one
+    /// that is still being handled by the webhook receiver.
+    pub async fn poll_once(&self) -> PollSummary {
+        let tracked: Vec<(TrackedInvoice, WebhookEvent)> = self
+            .tracked
+            .lock()
+            .expect("reconciler lock poisoned")
+            .iter()
+            .map(|(invoice, status)| (invoice.clone(), status.clone()))
+            .collect();
+
+        let mut summary = PollSummary::default();
+        for (invoice, last_status) in tracked {
+            match self.poll(&invoice, last_status).await {
+                Ok(Some(delivery)) => summary.dispatched.push(delivery),
+                Ok(None) => (),
+                Err(err) => summary.errors.push((invoice, err)),
+            }
+        }
+
+        summary
+    }
+
+    /// Poll every `interval`, forever
+    ///
+    /// Errors of a poll are passed to `on_error` with the failed invoice and
+    /// polling continues.
+    pub async fn run<F>(&self, interval: Duration, on_error: F)
+    where
+        F: Fn(&TrackedInvoice, NodelessError),
+    {
+        loop {
+            for (invoice, err) in self.poll_once().await.errors {
+                on_error(&invoice, err);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Poll `invoice` and dispatch its status if it changed
+    ///
+    /// A status recorded by [`observe`](Self::observe) while the poll was
+    /// running wins, the polled status may already be outdated.
+    async fn poll(
+        &self,
+        invoice: &TrackedInvoice,
+        last_status: WebhookEvent,
+    ) -> Result<Option<WebhookDelivery>, NodelessError> {
+        let status = self.source.status(invoice).await?;
+        if status == last_status || !self.is_current(invoice, &last_status) {
+            return Ok(None);
+        }
+
+        let delivery = synthesize(invoice, status.clone())?;
+        match &self.deduplicator {
+            Some(deduplicator) => {
+                let outcome = deduplicator
+                    .dispatch(&self.router, delivery.clone())
+                    .await?;
+                if outcome == DedupOutcome::InProgress {
+                    return Ok(None);
+                }
+            }
+            None => {
+                self.router.dispatch(delivery.clone()).await?;
+            }
+        }
+
+        let mut tracked = self.tracked.lock().expect("reconciler lock poisoned");
+        // Only advance from the status the poll started with
+        if tracked.get(invoice) == Some(&last_status) {
+            if is_final(&status) {
+                tracked.remove(invoice);
+            } else {
+                tracked.insert(invoice.clone(), status);
+            }
+        }
+        Ok(Some(delivery))
+    }
+
+    /// Check if `status` is still the last known status of `invoice`
+    fn is_current(&self, invoice: &TrackedInvoice, status: &WebhookEvent) -> bool {
+        self.tracked
+            .lock()
+            .expect("reconciler lock poisoned")
+            .get(invoice)
+            == Some(status)
+    }
+}
+
+/// Build a delivery for a status change detected by polling
+///
+/// The raw body is marked with `"synthesized": true`.
+fn synthesize(
+    invoice: &TrackedInvoice,
+    status: WebhookEvent,
+) -> Result<WebhookDelivery, NodelessError> {
+    let payload = WebhookPayload {
+        uuid: invoice.id().to_string(),
+        status,
+        sats_amount: None,
+        metadata: None,
+        created_at: None,
+        paid_at: None,
+    };
+
+    let mut raw = serde_json::to_value(&payload)?;
+    if let Value::Object(body) = &mut raw {
+        body.insert("synthesized".to_string(), Value::Bool(true));
+    }
+
+    Ok(WebhookDelivery {
+        type_: invoice.webhook_type(),
+        payload,
+        raw,
+    })
+}
+
+fn is_final(event: &WebhookEvent) -> bool {
+    InvoiceStatus::try_from(event.clone()).is_ok_and(|status| status.is_terminal())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::sync::Notify;
+
+    use super::*;
+
+    /// Status source answering from a map, failing for unknown invoices
+    #[derive(Default)]
+    struct FakeSource {
+        statuses: Mutex<HashMap<String, WebhookEvent>>,
+        gate: Option<Notify>,
+    }
+
+    impl FakeSource {
+        fn set(&self, id: &str, status: WebhookEvent) {
+            self.statuses.lock().unwrap().insert(id.to_string(), status);
+        }
+    }
+
+    impl StatusSource for FakeSource {
+        fn status<'a>(
+            &'a self,
+            invoice: &'a TrackedInvoice,
+        ) -> BoxFuture<'a, Result<WebhookEvent, NodelessError>> {
+            Box::pin(async move {
+                if let Some(gate) = &self.gate {
+                    gate.notified().await;
+                }
+                self.statuses
+                    .lock()
+                    .unwrap()
+                    .get(invoice.id())
+                    .cloned()
+                    .ok_or(NodelessError::InvalidResponse)
+            })
+        }
+    }
+
+    fn counting_router() -> (WebhookRouter, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let router = WebhookRouter::new().fallback(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Ok(()) }
+        });
+        (router, calls)
+    }
+
+    fn paid(invoice_id: &str) -> WebhookDelivery {
+        synthesize(
+            &TrackedInvoice::StoreInvoice {
+                store_id: "store-id".to_string(),
+                invoice_id: invoice_id.to_string(),
+            },
+            WebhookEvent::Paid,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn changed_status_is_synthesized() {
+        let source = Arc::new(FakeSource::default());
+        let (router, calls) = counting_router();
+        let reconciler = InvoiceReconciler::with_status_source(source.clone(), router);
+        reconciler.track_store_invoice("store-id", "invoice-1");
+        reconciler.track_paywall_request("paywall-id", "request-1");
+        source.set("invoice-1", WebhookEvent::PendingConfirmation);
+        source.set("request-1", WebhookEvent::New);
+
+        let summary = reconciler.poll_once().await;
+        assert!(summary.errors.is_empty());
+        assert_eq!(summary.dispatched.len(), 1);
+        let delivery = &summary.dispatched[0];
+        assert_eq!(delivery.type_, WebHookType::Store);
+        assert_eq!(delivery.payload.uuid, "invoice-1");
+        assert_eq!(delivery.event(), &WebhookEvent::PendingConfirmation);
+        assert_eq!(delivery.raw["synthesized"], true);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Unchanged statuses are not dispatched again
+        assert!(reconciler.poll_once().await.dispatched.is_empty());
+
+        // Final invoices are no longer tracked
+        source.set("invoice-1", WebhookEvent::Paid);
+        assert_eq!(reconciler.poll_once().await.dispatched.len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(reconciler.pending().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_invoices_do_not_stop_the_round() {
+        let source = Arc::new(FakeSource::default());
+        let reconciler =
+            InvoiceReconciler::with_status_source(source.clone(), WebhookRouter::new());
+        reconciler.track_store_invoice("store-id", "invoice-1");
+        reconciler.track_store_invoice("store-id", "invoice-2");
+        reconciler.track_paywall_request("paywall-id", "request-1");
+        source.set("invoice-2", WebhookEvent::Expired);
+
+        let summary = reconciler.poll_once().await;
+        assert_eq!(summary.dispatched.len(), 1);
+        assert_eq!(summary.errors.len(), 2);
+        assert_eq!(reconciler.pending().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn observed_webhook_is_not_rolled_back_by_poll() {
+        let source = Arc::new(FakeSource {
+            gate: Some(Notify::new()),
+            ..FakeSource::default()
+        });
+        let (router, calls) = counting_router();
+        let reconciler = InvoiceReconciler::with_status_source(source.clone(), router);
+        reconciler.track_store_invoice("store-id", "invoice-1");
+        reconciler.track_store_invoice("store-id", "invoice-2");
+        source.set("invoice-1", WebhookEvent::PendingConfirmation);
+        source.set("invoice-2", WebhookEvent::PendingConfirmation);
+
+        // The webhooks arrive while the statuses are being fetched
+        let (summary, ()) = tokio::join!(reconciler.poll_once(), async {
+            reconciler.observe(&paid("invoice-1"));
+            let mut pending = paid("invoice-2");
+            pending.payload.status = WebhookEvent::PendingConfirmation;
+            reconciler.observe(&pending);
+            let gate = source.gate.as_ref().unwrap();
+            gate.notify_one();
+            tokio::task::yield_now().await;
+            gate.notify_one();
+        });
+
+        assert!(summary.dispatched.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let pending = reconciler.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id(), "invoice-2");
+    }
+}
@@ -0,0 +1,352 @@
+//! Invoice Watcher
+//!
+//! Poll the status of a store invoice until it reaches a final state.
+//!
+//! # Example
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use futures::StreamExt;
+//! use nodeless_rs::invoice_watcher::WatchOptions;
+//! use nodeless_rs::Nodeless;
+//!
+//! # async fn run() -> Result<(), nodeless_rs::error::NodelessError> {
+//! let nodeless = Nodeless::new("xxxxxxxxxxx", None)?;
+//!
+//! let mut statuses = Box::pin(nodeless.watch_store_invoice("store-id", "invoice-id"));
+//! while let Some(status) = statuses.next().await {
+//!     println!("{:?}", status?);
+//! }
+//!
+//! let status = nodeless
+//!     .wait_for_invoice_paid(
+//!         "store-id",
+//!         "invoice-id",
+//!         Duration::from_secs(600),
+//!         WatchOptions::default(),
+//!     )
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::time::Duration;
+
+use futures::stream::{self, Stream};
+
+use crate::error::NodelessError;
+use crate::store::InvoiceStatus;
+use crate::Nodeless;
+
+/// Polling options of an invoice watcher
+#[derive(Clone, Debug)]
+pub struct WatchOptions {
+    /// Delay between polls after a status change
+    pub interval: Duration,
+    /// Upper bound of the delay between polls
+    pub max_interval: Duration,
+    /// Factor the delay grows by while the status is unchanged
+    ///
+    /// Factors below 1 and non finite ones keep the delay unchanged.
+    pub backoff: f64,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            max_interval: Duration::from_secs(30),
+            backoff: 1.5,
+        }
+    }
+}
+
+struct WatchState {
+    client: Nodeless,
+    store_id: String,
+    invoice_id: String,
+    options: WatchOptions,
+    last: Option<InvoiceStatus>,
+    delay: Duration,
+    first: bool,
+    done: bool,
+}
+
+impl WatchState {
+    fn back_off(&mut self) {
+        let backoff = match self.options.backoff {
+            backoff if backoff.is_finite() && backoff >= 1.0 => backoff,
+            _ => 1.0,
+        };
+        self.delay = Duration::try_from_secs_f64(self.delay.as_secs_f64() * backoff)
+            .unwrap_or(self.options.max_interval)
+            .min(self.options.max_interval);
+    }
+}
+
+impl Nodeless {
+    /// Watch the status of a store invoice with default [`WatchOptions`]
+    ///
+    /// See [`Nodeless::watch_store_invoice_with`].
+    pub fn watch_store_invoice(
+        &self,
+        store_id: &str,
+        invoice_id: &str,
+    ) -> impl Stream<Item = Result<InvoiceStatus, NodelessError>> + Send + 'static {
+        self.watch_store_invoice_with(store_id, invoice_id, WatchOptions::default())
+    }
+
+    /// Watch the status of a store invoice
+    ///
+    /// Yields the current status, then every change of it. Failed polls are
+    /// yielded as errors and polling continues with a longer delay. The stream
+    /// ends after a final status, drop it to stop watching earlier.
+    pub fn watch_store_invoice_with(
+        &self,
+        store_id: &str,
+        invoice_id: &str,
+        options: WatchOptions,
+    ) -> impl Stream<Item = Result<InvoiceStatus, NodelessError>> + Send + 'static {
+        let state = WatchState {
+            client: self.clone(),
+            store_id: store_id.to_string(),
+            invoice_id: invoice_id.to_string(),
+            delay: options.interval,
+            options,
+            last: None,
+            first: true,
+            done: false,
+        };
+
+        stream::unfold(state, |mut state| async move {
+            if state.done {
+                return None;
+            }
+
+            loop {
+                if !state.first {
+                    tokio::time::sleep(state.delay).await;
+                }
+                state.first = false;
+
+                let status = match state
+                    .client
+                    .get_store_invoice_status(&state.store_id, &state.invoice_id)
+                    .await
+                {
+                    Ok(status) => status,
+                    Err(err) => {
+                        state.back_off();
+                        return Some((Err(err), state));
+                    }
+                };
+
+                if state.last.as_ref() == Some(&status) {
+                    state.back_off();
+                    continue;
+                }
+
+                state.delay = state.options.interval;
+                state.done = status.is_terminal();
+                state.last = Some(status.clone());
+                return Some((Ok(status), state));
+            }
+        })
+    }
+
+    /// Wait until a store invoice is paid
+    ///
+    /// Returns the settled status, [`InvoiceStatus::Paid`] or
+    /// [`InvoiceStatus::Overpaid`]. Other final statuses are returned as
+    /// [`NodelessError::InvoiceNotPaid`] and [`NodelessError::Timeout`] once
+    /// `timeout` has elapsed. Failed polls are retried as configured by
+    /// `options` while [`NodelessError::is_retryable`], other errors are
+    /// returned immediately.
+    pub async fn wait_for_invoice_paid(
+        &self,
+        store_id: &str,
+        invoice_id: &str,
+        timeout: Duration,
+        options: WatchOptions,
+    ) -> Result<InvoiceStatus, NodelessError> {
+        use futures::StreamExt;
+
+        let watch = async {
+            let mut statuses =
+                Box::pin(self.watch_store_invoice_with(store_id, invoice_id, options));
+            let mut last = None;
+            while let Some(status) = statuses.next().await {
+                match status {
+                    Ok(status) => last = Some(status),
+                    Err(err) if err.is_retryable() => (),
+                    Err(err) => return Err(err),
+                }
+            }
+            match last {
+                Some(status) if status.is_settled() => Ok(status),
+                Some(status) => Err(NodelessError::InvoiceNotPaid(status)),
+                None => Err(NodelessError::InvalidResponse),
+            }
+        };
+
+        tokio::time::timeout(timeout, watch)
+            .await
+            .map_err(|_| NodelessError::Timeout)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// Serve `responses` in order, repeating the last one, and count requests
+    fn serve(responses: Vec<(&'static str, &'static str)>) -> (Nodeless, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let n = count.fetch_add(1, Ordering::SeqCst);
+                let (status, body) = responses[n.min(responses.len() - 1)];
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+        (Nodeless::new("api-key", Some(url)).unwrap(), requests)
+    }
+
+    fn fast() -> WatchOptions {
+        WatchOptions {
+            interval: Duration::from_millis(10),
+            max_interval: Duration::from_millis(10),
+            backoff: 1.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_returns_non_retryable_error() {
+        let (client, requests) = serve(vec![(
+            "401 Unauthorized",
+            r#"{"message":"Unauthenticated."}"#,
+        )]);
+
+        let err = client
+            .wait_for_invoice_paid("store-id", "invoice-id", Duration::from_secs(5), fast())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, NodelessError::SerdeError(_)), "{err:?}");
+        assert!(!err.is_retryable());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn wait_retries_transient_errors() {
+        let (client, requests) = serve(vec![
+            ("502 Bad Gateway", "<html>bad gateway</html>"),
+            ("503 Service Unavailable", "<html>unavailable</html>"),
+            ("200 OK", r#"{"status":"new"}"#),
+            ("200 OK", r#"{"status":"paid"}"#),
+        ]);
+
+        let status = client
+            .wait_for_invoice_paid("store-id", "invoice-id", Duration::from_secs(5), fast())
+            .await
+            .unwrap();
+        assert_eq!(status, InvoiceStatus::Paid);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn wait_reports_unpaid_final_status() {
+        let (client, _) = serve(vec![("200 OK", r#"{"status":"expired"}"#)]);
+
+        let err = client
+            .wait_for_invoice_paid("store-id", "invoice-id", Duration::from_secs(5), fast())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            NodelessError::InvoiceNotPaid(InvoiceStatus::Expired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_retryable() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = Nodeless::new("api-key", Some(format!("http://127.0.0.1:{port}"))).unwrap();
+
+        let err = client
+            .get_store_invoice_status("store-id", "invoice-id")
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable(), "{err:?}");
+    }
+
+    fn state(backoff: f64) -> WatchState {
+        let options = WatchOptions {
+            interval: Duration::from_secs(2),
+            max_interval: Duration::from_secs(30),
+            backoff,
+        };
+        WatchState {
+            client: Nodeless::new("api-key", None).unwrap(),
+            store_id: "store-id".to_string(),
+            invoice_id: "invoice-id".to_string(),
+            delay: options.interval,
+            options,
+            last: None,
+            first: true,
+            done: false,
+        }
+    }
+
+    #[test]
+    fn back_off_grows_up_to_max_interval() {
+        let mut state = state(2.0);
+        state.back_off();
+        assert_eq!(state.delay, Duration::from_secs(4));
+        for _ in 0..10 {
+            state.back_off();
+        }
+        assert_eq!(state.delay, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn invalid_back_off_keeps_delay() {
+        for backoff in [-1.0, 0.5, f64::NAN, f64::INFINITY] {
+            let mut state = state(backoff);
+            state.back_off();
+            assert_eq!(state.delay, Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn huge_back_off_is_capped() {
+        let mut state = state(f64::MAX);
+        state.back_off();
+        assert_eq!(state.delay, Duration::from_secs(30));
+    }
+}
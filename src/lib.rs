@@ -8,14 +8,34 @@ use reqwest::{Client, Url};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+pub mod amount;
+pub mod bip21;
+#[cfg(feature = "bolt11")]
+pub mod bolt11;
+pub mod cloudevents;
+pub mod currency;
 pub mod error;
+pub mod invoice_expiry;
+pub mod invoice_lifecycle;
+pub mod invoice_reconciler;
+pub mod invoice_watcher;
 pub mod paywall;
 pub mod paywall_webhook;
+#[cfg(feature = "qr")]
+pub mod qr;
 pub mod serde_utils;
 pub mod store;
 pub mod store_webhook;
 pub mod transaction;
 pub mod webhook;
+pub mod webhook_dedup;
+pub mod webhook_enrich;
+pub mod webhook_inventory;
+pub mod webhook_queue;
+pub mod webhook_reconcile;
+pub mod webhook_router;
+pub mod webhook_secret;
+pub mod webhook_simulator;
 
 #[derive(Debug, Clone)]
 pub struct Nodeless {
@@ -1,23 +1,67 @@
 //! Paywall
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 
+use crate::amount::Sats;
 use crate::error::NodelessError;
-use crate::serde_utils::{opt_serde_timestamp, serde_timestamp};
+use crate::serde_utils::{opt_metadata, opt_serde_timestamp, serde_timestamp};
 use crate::Nodeless;
 
 /// Paywall Types
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PaywallType {
-    #[serde(rename = "content")]
     Content,
-    #[serde(rename = "download")]
     Download,
-    #[serde(rename = "redirect")]
     Redirect,
-    #[serde(rename = "wp_article")]
     WPArticle,
+    Unknown(String),
+}
+
+impl PaywallType {
+    /// Name of the paywall type as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            PaywallType::Content => "content",
+            PaywallType::Download => "download",
+            PaywallType::Redirect => "redirect",
+            PaywallType::WPArticle => "wp_article",
+            PaywallType::Unknown(type_) => type_,
+        }
+    }
+}
+
+impl From<&str> for PaywallType {
+    fn from(type_: &str) -> Self {
+        match type_ {
+            "content" => PaywallType::Content,
+            "download" => PaywallType::Download,
+            "redirect" => PaywallType::Redirect,
+            "wp_article" => PaywallType::WPArticle,
+            _ => PaywallType::Unknown(type_.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PaywallType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let type_ = String::deserialize(deserializer)?;
+        Ok(PaywallType::from(type_.as_str()))
+    }
+}
+
+impl Serialize for PaywallType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
 }
 
 /// Paywall
@@ -28,7 +72,7 @@ pub struct Paywall {
     pub name: Option<String>,
     #[serde(rename = "type")]
     pub type_: PaywallType,
-    pub price: u64,
+    pub price: Sats,
     pub settings: Option<HashMap<String, String>>,
     #[serde(with = "opt_serde_timestamp")]
     pub created_at: Option<i64>,
@@ -37,13 +81,18 @@ pub struct Paywall {
 }
 
 /// Paywall Request
+///
+/// `M` is the type of the metadata, a list of strings by default. The api
+/// may also send an object, use [`RawPaywallRequest`] to accept either.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct PaywallRequest {
+#[serde(bound(serialize = "M: Serialize", deserialize = "M: DeserializeOwned"))]
+pub struct PaywallRequest<M = Vec<String>> {
     pub id: String,
-    pub sats_amount: u64,
+    pub sats_amount: Sats,
     pub status: String,
-    pub metadata: Option<Vec<String>>,
+    #[serde(default, with = "opt_metadata")]
+    pub metadata: Option<M>,
     #[serde(with = "serde_timestamp")]
     pub created_at: i64,
     #[serde(with = "opt_serde_timestamp")]
@@ -53,6 +102,9 @@ pub struct PaywallRequest {
     pub paywall: Option<Paywall>,
 }
 
+/// Paywall Request keeping the metadata json sent by the api
+pub type RawPaywallRequest = PaywallRequest<Value>;
+
 impl Nodeless {
     /// Create Paywall
     pub async fn create_paywall(&self, paywall: Paywall) -> Result<Paywall, NodelessError> {
@@ -119,6 +171,19 @@ impl Nodeless {
         paywall_id: &str,
         request_id: &str,
     ) -> Result<PaywallRequest, NodelessError> {
+        self.get_paywall_request_with_metadata(paywall_id, request_id)
+            .await
+    }
+
+    /// Get a Paywall Request with metadata of type `M`
+    pub async fn get_paywall_request_with_metadata<M>(
+        &self,
+        paywall_id: &str,
+        request_id: &str,
+    ) -> Result<PaywallRequest<M>, NodelessError>
+    where
+        M: DeserializeOwned,
+    {
         let url = self
             .base_url
             .join(&format!("api/v1/paywall/{paywall_id}/request/{request_id}"))?;
@@ -142,3 +207,65 @@ impl Nodeless {
         Ok(serde_json::from_value(res["status"].to_owned())?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(metadata: Value) -> Value {
+        json!({
+            "id": "request-id",
+            "satsAmount": 2100,
+            "status": "new",
+            "metadata": metadata,
+            "createdAt": "2023-06-01T12:00:00.000000Z",
+            "paidAt": null,
+            "onchainAddress": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "lightningInvoice": "lnbc21u1p...",
+            "paywall": null
+        })
+    }
+
+    #[test]
+    fn request_metadata_list() {
+        let parsed: PaywallRequest = serde_json::from_value(request(json!(["foo"]))).unwrap();
+        assert_eq!(parsed.metadata, Some(vec!["foo".to_string()]));
+
+        let parsed: RawPaywallRequest = serde_json::from_value(request(json!(["foo"]))).unwrap();
+        assert_eq!(parsed.metadata, Some(json!(["foo"])));
+
+        let parsed: PaywallRequest = serde_json::from_value(request(json!([]))).unwrap();
+        assert_eq!(parsed.metadata, None);
+    }
+
+    #[test]
+    fn request_metadata_object() {
+        let parsed: RawPaywallRequest =
+            serde_json::from_value(request(json!({"order_id": "1042"}))).unwrap();
+        assert_eq!(parsed.metadata, Some(json!({"order_id": "1042"})));
+        assert!(
+            serde_json::from_value::<PaywallRequest>(request(json!({"order_id": "1042"}))).is_err()
+        );
+
+        let parsed: PaywallRequest<HashMap<String, String>> =
+            serde_json::from_value(request(json!({"order_id": "1042"}))).unwrap();
+        assert_eq!(parsed.metadata.unwrap()["order_id"], "1042");
+    }
+
+    #[test]
+    fn paywall_type_round_trips() {
+        for (type_, json) in [
+            (PaywallType::Content, "\"content\""),
+            (PaywallType::Download, "\"download\""),
+            (PaywallType::Redirect, "\"redirect\""),
+            (PaywallType::WPArticle, "\"wp_article\""),
+            (PaywallType::Unknown("video".to_string()), "\"video\""),
+        ] {
+            assert_eq!(serde_json::to_string(&type_).unwrap(), json);
+            assert_eq!(serde_json::from_str::<PaywallType>(json).unwrap(), type_);
+            assert_eq!(PaywallType::from(type_.as_str()), type_);
+        }
+    }
+}
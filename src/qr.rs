@@ -0,0 +1,312 @@
+//! QR Codes
+//!
+//! Renders the payment data of invoices and paywall requests as QR codes,
+//! to SVG, PNG or Unicode blocks for terminals.
+//!
+//! # Example
+//! ```
+//! use nodeless_rs::qr::{ErrorCorrection, QrImage, QrOptions};
+//!
+//! let options = QrOptions {
+//!     module_size: 4,
+//!     error_correction: ErrorCorrection::Low,
+//!     ..Default::default()
+//! };
+//! let qr = QrImage::new("bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", options).unwrap();
+//!
+//! let svg = qr.to_svg();
+//! let png = qr.to_png().unwrap();
+//! println!("{}", qr.to_terminal());
+//! ```
+use qrcode::{Color, EcLevel, QrCode};
+
+use crate::error::NodelessError;
+use crate::paywall::PaywallRequest;
+use crate::store::QrCodes;
+
+/// Error correction level, higher levels survive more damage but need more
+/// modules
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ErrorCorrection {
+    /// Recovers 7% of the data
+    Low,
+    /// Recovers 15% of the data
+    #[default]
+    Medium,
+    /// Recovers 25% of the data
+    Quartile,
+    /// Recovers 30% of the data
+    High,
+}
+
+impl From<ErrorCorrection> for EcLevel {
+    fn from(level: ErrorCorrection) -> Self {
+        match level {
+            ErrorCorrection::Low => EcLevel::L,
+            ErrorCorrection::Medium => EcLevel::M,
+            ErrorCorrection::Quartile => EcLevel::Q,
+            ErrorCorrection::High => EcLevel::H,
+        }
+    }
+}
+
+/// Rendering options of a QR code
+#[derive(Clone, Debug)]
+pub struct QrOptions {
+    /// Size of a module in pixels for PNG and SVG
+    pub module_size: u32,
+    /// Width of the quiet zone around the code in modules
+    pub margin: u32,
+    pub error_correction: ErrorCorrection,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            module_size: 8,
+            margin: 4,
+            error_correction: ErrorCorrection::Medium,
+        }
+    }
+}
+
+/// Encoded QR code
+#[derive(Clone, Debug)]
+pub struct QrImage {
+    width: usize,
+    dark: Vec<bool>,
+    options: QrOptions,
+}
+
+impl QrImage {
+    /// Encode `data`
+    pub fn new(data: &str, options: QrOptions) -> Result<Self, NodelessError> {
+        let code = QrCode::with_error_correction_level(data, options.error_correction.into())
+            .map_err(|err| NodelessError::QrCodeError(err.to_string()))?;
+
+        Ok(Self {
+            width: code.width(),
+            dark: code
+                .into_colors()
+                .into_iter()
+                .map(|color| color == Color::Dark)
+                .collect(),
+            options,
+        })
+    }
+
+    /// Number of modules per side, without margin
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Render as SVG document
+    pub fn to_svg(&self) -> String {
+        let margin = self.options.margin as usize;
+        let size = self.width + 2 * margin;
+        let pixels = size * self.options.module_size.max(1) as usize;
+
+        let mut path = String::new();
+        for y in 0..self.width {
+            for x in 0..self.width {
+                if self.dark[y * self.width + x] {
+                    path.push_str(&format!("M{} {}h1v1h-1z", x + margin, y + margin));
+                }
+            }
+        }
+
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{pixels}" height="{pixels}" viewBox="0 0 {size} {size}" shape-rendering="crispEdges"><rect width="{size}" height="{size}" fill="#ffffff"/><path fill="#000000" d="{path}"/></svg>"##
+        )
+    }
+
+    /// Render as grayscale PNG
+    pub fn to_png(&self) -> Result<Vec<u8>, NodelessError> {
+        let margin = self.options.margin as usize;
+        let module_size = self.options.module_size.max(1) as usize;
+        let pixels = (self.width + 2 * margin) * module_size;
+
+        let mut data = vec![255u8; pixels * pixels];
+        for y in 0..self.width {
+            for x in 0..self.width {
+                if !self.dark[y * self.width + x] {
+                    continue;
+                }
+                for py in 0..module_size {
+                    let row = ((y + margin) * module_size + py) * pixels;
+                    let start = row + (x + margin) * module_size;
+                    data[start..start + module_size].fill(0);
+                }
+            }
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, pixels as u32, pixels as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(|err| NodelessError::QrCodeError(err.to_string()))?;
+
+        Ok(png)
+    }
+
+    /// Render with Unicode half blocks, two rows of modules per line
+    ///
+    /// Light modules are drawn, so the code scans on terminals with a dark
+    /// background.
+    pub fn to_terminal(&self) -> String {
+        let margin = self.options.margin as usize;
+        let size = self.width + 2 * margin;
+        let is_light = |x: usize, y: usize| {
+            if y >= size {
+                return false;
+            }
+            if x < margin || y < margin || x >= margin + self.width || y >= margin + self.width {
+                return true;
+            }
+            !self.dark[(y - margin) * self.width + (x - margin)]
+        };
+
+        let mut out = String::new();
+        for y in (0..size).step_by(2) {
+            for x in 0..size {
+                out.push(match (is_light(x, y), is_light(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl QrCodes {
+    /// QR code of the unified BIP21 uri
+    pub fn unified_qr(&self, options: QrOptions) -> Result<QrImage, NodelessError> {
+        QrImage::new(&self.unified, options)
+    }
+
+    /// QR code of the onchain address
+    pub fn onchain_qr(&self, options: QrOptions) -> Result<QrImage, NodelessError> {
+        QrImage::new(&self.onchain, options)
+    }
+
+    /// QR code of the lightning invoice
+    ///
+    /// The invoice is encoded in upper case, which needs fewer modules.
+    pub fn lightning_qr(&self, options: QrOptions) -> Result<QrImage, NodelessError> {
+        QrImage::new(&self.lightning.to_uppercase(), options)
+    }
+}
+
+impl<M> PaywallRequest<M> {
+    /// QR code of the lightning invoice
+    ///
+    /// The invoice is encoded in upper case, which needs fewer modules.
+    pub fn lightning_qr(&self, options: QrOptions) -> Result<QrImage, NodelessError> {
+        QrImage::new(&self.lightning_invoice.to_uppercase(), options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+    fn qr(module_size: u32, margin: u32) -> QrImage {
+        let options = QrOptions {
+            module_size,
+            margin,
+            ..Default::default()
+        };
+        QrImage::new(ADDRESS, options).unwrap()
+    }
+
+    #[test]
+    fn png_has_margin_and_module_size() {
+        for (module_size, margin) in [(1, 0), (4, 2), (8, 4)] {
+            let qr = qr(module_size, margin);
+            let png = qr.to_png().unwrap();
+
+            let reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+            let info = reader.info();
+            let pixels = (qr.width() as u32 + 2 * margin) * module_size;
+            assert_eq!((info.width, info.height), (pixels, pixels));
+            assert_eq!(info.color_type, png::ColorType::Grayscale);
+        }
+    }
+
+    #[test]
+    fn png_modules_are_drawn() {
+        let qr = qr(2, 1);
+        let png = qr.to_png().unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut data).unwrap();
+        let pixels = frame.width as usize;
+
+        // Margin is light, the top left finder pattern starts dark
+        assert_eq!(data[0], 255);
+        assert_eq!(data[2 * pixels + 2], 0);
+        assert_eq!(data[3 * pixels + 3], 0);
+    }
+
+    #[test]
+    fn svg_view_box_and_path() {
+        let qr = qr(4, 2);
+        let svg = qr.to_svg();
+        let size = qr.width() + 4;
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(&format!(r#"viewBox="0 0 {size} {size}""#)));
+        assert!(svg.contains(&format!(r#"width="{}""#, size * 4)));
+        let dark = qr.dark.iter().filter(|dark| **dark).count();
+        assert_eq!(svg.matches("h1v1h-1z").count(), dark);
+        // Top left module of the finder pattern, offset by the margin
+        assert!(svg.contains(r#"d="M2 2h1v1h-1z"#));
+    }
+
+    #[test]
+    fn terminal_draws_two_rows_per_line() {
+        for margin in [0, 1, 4] {
+            let qr = qr(1, margin);
+            let size = qr.width() + 2 * margin as usize;
+            let terminal = qr.to_terminal();
+            let lines: Vec<&str> = terminal.lines().collect();
+
+            assert_eq!(lines.len(), size.div_ceil(2), "margin {margin}");
+            assert!(lines.iter().all(|line| line.chars().count() == size));
+        }
+    }
+
+    #[test]
+    fn oversized_data_is_an_error() {
+        let data = "x".repeat(8000);
+        assert!(matches!(
+            QrImage::new(&data, QrOptions::default()),
+            Err(NodelessError::QrCodeError(_))
+        ));
+    }
+
+    #[test]
+    fn lightning_invoice_is_encoded_upper_case() {
+        let invoice = "lightning:lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+        let codes = QrCodes {
+            unified: String::new(),
+            onchain: String::new(),
+            lightning: invoice.to_string(),
+        };
+
+        let lightning = codes.lightning_qr(QrOptions::default()).unwrap();
+        let upper = QrImage::new(&invoice.to_uppercase(), QrOptions::default()).unwrap();
+        let lower = QrImage::new(invoice, QrOptions::default()).unwrap();
+        assert_eq!(lightning.dark, upper.dark);
+        assert!(lightning.width() < lower.width());
+    }
+}
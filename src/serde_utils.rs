@@ -57,10 +57,7 @@ pub mod serde_timestamp {
     where
         S: Serializer,
     {
-        let datetime = DateTime::<Utc>::from_utc(
-            chrono::NaiveDateTime::from_timestamp_opt(*date, 0).unwrap(),
-            Utc,
-        );
+        let datetime = DateTime::<Utc>::from_timestamp(*date, 0).unwrap();
         let s = format!("{}", datetime.format(FORMAT));
         serializer.serialize_str(&s)
     }
@@ -89,10 +86,7 @@ pub mod opt_serde_timestamp {
     {
         match date {
             Some(d) => {
-                let datetime = DateTime::<Utc>::from_utc(
-                    chrono::NaiveDateTime::from_timestamp_opt(*d, 0).unwrap(),
-                    Utc,
-                );
+                let datetime = DateTime::<Utc>::from_timestamp(*d, 0).unwrap();
                 let s = format!("{}", datetime.format(FORMAT));
                 serializer.serialize_str(&s)
             }
@@ -116,3 +110,68 @@ pub mod opt_serde_timestamp {
         }
     }
 }
+
+pub mod serde_decimal {
+    use std::str::FromStr;
+
+    use rust_decimal::Decimal;
+    use serde::{self, Deserialize, Deserializer, Serializer};
+    use serde_json::Value;
+
+    /// Serialize as numeric string, which keeps every digit
+    pub fn serialize<S>(decimal: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(decimal)
+    }
+
+    /// Deserialize from a json number or numeric string
+    ///
+    /// Both may use scientific notation, such as `1e-7`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Number(number) => parse(&number.to_string()).map_err(serde::de::Error::custom),
+            Value::String(s) => parse(&s).map_err(serde::de::Error::custom),
+            value => Err(serde::de::Error::custom(format!(
+                "expected decimal amount, found {value}"
+            ))),
+        }
+    }
+
+    fn parse(s: &str) -> Result<Decimal, rust_decimal::Error> {
+        Decimal::from_str(s).or_else(|_| Decimal::from_scientific(s))
+    }
+}
+
+pub mod opt_metadata {
+    use serde::de::DeserializeOwned;
+    use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S, M>(metadata: &Option<M>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        M: Serialize,
+    {
+        metadata.serialize(serializer)
+    }
+
+    /// Empty metadata is sent as `[]` by the api and deserialized as `None`
+    pub fn deserialize<'de, D, M>(deserializer: D) -> Result<Option<M>, D::Error>
+    where
+        D: Deserializer<'de>,
+        M: DeserializeOwned,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Null => Ok(None),
+            Value::Array(values) if values.is_empty() => Ok(None),
+            value => serde_json::from_value(value)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
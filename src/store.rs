@@ -1,11 +1,17 @@
 //! Store
 use std::collections::HashMap;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use url::Url;
 
+use crate::amount::{FiatAmount, Sats};
+use crate::currency::Currency;
 use crate::error::NodelessError;
-use crate::serde_utils::{opt_serde_timestamp, opt_serde_url, serde_timestamp, serde_url};
+use crate::serde_utils::{
+    opt_metadata, opt_serde_timestamp, opt_serde_url, serde_timestamp, serde_url,
+};
+use crate::webhook::WebhookEvent;
 use crate::Nodeless;
 
 /// Store
@@ -21,32 +27,335 @@ pub struct Store {
     pub created_at: i64,
 }
 
+/// Invoice metadata used when no other type is given
+pub type Metadata = HashMap<String, String>;
+
 /// Invoice Request
+///
+/// `M` is the type of the metadata, any type serializing to a json object.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct InvoiceRequest {
-    pub amount: f64,
-    pub currency: String,
+#[serde(bound(serialize = "M: Serialize", deserialize = "M: DeserializeOwned"))]
+pub struct InvoiceRequest<M = Metadata> {
+    pub amount: FiatAmount,
+    pub currency: Currency,
     pub buyer_email: String,
     #[serde(with = "serde_url")]
     pub redirect_url: Url,
-    pub metadata: Option<HashMap<String, String>>,
+    #[serde(default, with = "opt_metadata")]
+    pub metadata: Option<M>,
+}
+
+impl InvoiceRequest {
+    /// Create validating builder
+    pub fn builder() -> InvoiceRequestBuilder {
+        InvoiceRequestBuilder::default()
+    }
+}
+
+impl<M> InvoiceRequest<M> {
+    /// Replace the metadata with typed `metadata`
+    ///
+    /// `metadata` must serialize to a json object within the default
+    /// [`MetadataLimits`]. Values that are not strings count with the length
+    /// of their json.
+    pub fn with_metadata<N>(self, metadata: N) -> Result<InvoiceRequest<N>, NodelessError>
+    where
+        N: Serialize,
+    {
+        self.with_metadata_within(metadata, &MetadataLimits::default())
+    }
+
+    /// Replace the metadata with typed `metadata` within `limits`
+    pub fn with_metadata_within<N>(
+        self,
+        metadata: N,
+        limits: &MetadataLimits,
+    ) -> Result<InvoiceRequest<N>, NodelessError>
+    where
+        N: Serialize,
+    {
+        match serde_json::to_value(&metadata)? {
+            serde_json::Value::Object(entries) => {
+                limits.check(entries.iter().map(|(key, value)| {
+                    let length = match value {
+                        serde_json::Value::String(value) => value.chars().count(),
+                        value => value.to_string().chars().count(),
+                    };
+                    (key.as_str(), length)
+                }))?;
+            }
+            _ => return Err(invalid_invoice("metadata must be a json object")),
+        }
+
+        Ok(InvoiceRequest {
+            amount: self.amount,
+            currency: self.currency,
+            buyer_email: self.buyer_email,
+            redirect_url: self.redirect_url,
+            metadata: Some(metadata),
+        })
+    }
+}
+
+/// Default maximum number of metadata entries, see [`MetadataLimits`]
+pub const MAX_METADATA_KEYS: usize = 20;
+
+/// Default maximum length of a metadata key, see [`MetadataLimits`]
+pub const MAX_METADATA_KEY_LENGTH: usize = 40;
+
+/// Default maximum length of a metadata value, see [`MetadataLimits`]
+pub const MAX_METADATA_VALUE_LENGTH: usize = 500;
+
+/// Limits of invoice metadata checked before a request is sent
+///
+/// The api documentation does not publish metadata limits. The defaults are
+/// conservative ones of this crate meant to catch runaway metadata early,
+/// raise them if the api accepts what your store needs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataLimits {
+    /// Maximum number of entries
+    pub max_keys: usize,
+    /// Maximum length of a key in characters
+    pub max_key_length: usize,
+    /// Maximum length of a value in characters
+    pub max_value_length: usize,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        Self {
+            max_keys: MAX_METADATA_KEYS,
+            max_key_length: MAX_METADATA_KEY_LENGTH,
+            max_value_length: MAX_METADATA_VALUE_LENGTH,
+        }
+    }
+}
+
+impl MetadataLimits {
+    /// Check the limits, given the keys and the lengths of their values
+    fn check<'a>(
+        &self,
+        entries: impl ExactSizeIterator<Item = (&'a str, usize)>,
+    ) -> Result<(), NodelessError> {
+        if entries.len() > self.max_keys {
+            return Err(invalid_invoice(&format!(
+                "at most {} metadata entries are allowed",
+                self.max_keys
+            )));
+        }
+        for (key, length) in entries {
+            if key.is_empty() || key.chars().count() > self.max_key_length {
+                return Err(invalid_invoice(&format!(
+                    "metadata keys must have 1 to {} characters",
+                    self.max_key_length
+                )));
+            }
+            if length > self.max_value_length {
+                return Err(invalid_invoice(&format!(
+                    "metadata value of {key} exceeds {} characters",
+                    self.max_value_length
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Store level defaults of invoice requests
+#[derive(Clone, Debug, Default)]
+pub struct InvoiceDefaults {
+    pub currency: Option<Currency>,
+    pub redirect_url: Option<Url>,
+    pub metadata: Metadata,
+    /// Metadata limits, [`MetadataLimits::default`] if not set
+    pub metadata_limits: Option<MetadataLimits>,
+}
+
+impl InvoiceDefaults {
+    /// Defaults redirecting to the website of `store`
+    pub fn from_store(store: &Store) -> Self {
+        Self {
+            redirect_url: store.url.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Invoice Request Builder
+///
+/// # Example
+/// ```
+/// use std::str::FromStr;
+///
+/// use nodeless_rs::amount::FiatAmount;
+/// use nodeless_rs::currency::Currency;
+/// use nodeless_rs::store::InvoiceRequest;
+/// use url::Url;
+///
+/// let request = InvoiceRequest::builder()
+///     .amount(FiatAmount::from_str("21.21").unwrap())
+///     .currency(Currency::USD)
+///     .buyer_email("hi@nodeless.io")
+///     .redirect_url(Url::from_str("https://nodeless.io").unwrap())
+///     .metadata("order_id", "1042")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InvoiceRequestBuilder {
+    amount: Option<FiatAmount>,
+    currency: Option<Currency>,
+    buyer_email: Option<String>,
+    redirect_url: Option<Url>,
+    metadata: Metadata,
+    metadata_limits: Option<MetadataLimits>,
+    defaults: InvoiceDefaults,
+}
+
+impl InvoiceRequestBuilder {
+    /// Set amount in `currency`
+    pub fn amount(mut self, amount: FiatAmount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    /// Set currency
+    pub fn currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    /// Set email of the buyer
+    pub fn buyer_email(mut self, buyer_email: &str) -> Self {
+        self.buyer_email = Some(buyer_email.to_string());
+        self
+    }
+
+    /// Set url the buyer is redirected to after paying
+    pub fn redirect_url(mut self, redirect_url: Url) -> Self {
+        self.redirect_url = Some(redirect_url);
+        self
+    }
+
+    /// Add metadata entry
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set limits the metadata is checked against
+    pub fn metadata_limits(mut self, limits: MetadataLimits) -> Self {
+        self.metadata_limits = Some(limits);
+        self
+    }
+
+    /// Use `defaults` for fields that are not set
+    ///
+    /// Metadata entries of the defaults are merged, entries set on the
+    /// builder take precedence.
+    pub fn defaults(mut self, defaults: InvoiceDefaults) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Validate and build [`InvoiceRequest`]
+    pub fn build(self) -> Result<InvoiceRequest, NodelessError> {
+        let currency = self
+            .currency
+            .or(self.defaults.currency)
+            .ok_or_else(|| invalid_invoice("currency is required"))?;
+        let amount = self
+            .amount
+            .ok_or_else(|| invalid_invoice("amount is required"))?;
+        let buyer_email = self
+            .buyer_email
+            .ok_or_else(|| invalid_invoice("buyer email is required"))?;
+        let redirect_url = self
+            .redirect_url
+            .or(self.defaults.redirect_url)
+            .ok_or_else(|| invalid_invoice("redirect url is required"))?;
+
+        currency.validate_amount(amount)?;
+
+        if !is_valid_email(&buyer_email) {
+            return Err(invalid_invoice("buyer email is invalid"));
+        }
+
+        if !matches!(redirect_url.scheme(), "http" | "https") {
+            return Err(invalid_invoice("redirect url must use http or https"));
+        }
+
+        let mut metadata = self.defaults.metadata;
+        metadata.extend(self.metadata);
+        self.metadata_limits
+            .or(self.defaults.metadata_limits)
+            .unwrap_or_default()
+            .check(
+                metadata
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.chars().count())),
+            )?;
+
+        Ok(InvoiceRequest {
+            amount,
+            currency,
+            buyer_email,
+            redirect_url,
+            metadata: (!metadata.is_empty()).then_some(metadata),
+        })
+    }
+}
+
+fn invalid_invoice(reason: &str) -> NodelessError {
+    NodelessError::InvalidInvoice(reason.to_string())
+}
+
+/// Syntax check of an email address, without resolving the domain
+fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+
+    let valid_local = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_domain = domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+
+    valid_local && valid_domain
 }
 
 /// Invoice
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct Invoice {
+#[serde(bound(serialize = "M: Serialize", deserialize = "M: DeserializeOwned"))]
+pub struct Invoice<M = Metadata> {
     pub id: Option<String>,
     #[serde(with = "opt_serde_url")]
     #[serde(rename = "checkoutLink")]
     pub checkout_link: Option<Url>,
-    pub sats_amount: u64,
+    pub sats_amount: Sats,
     pub status: InvoiceStatus,
     pub buyer_email: String,
     #[serde(with = "serde_url")]
     pub redirect_url: Url,
-    pub metadata: Option<HashMap<String, String>>,
+    #[serde(default, with = "opt_metadata")]
+    pub metadata: Option<M>,
     #[serde(with = "serde_timestamp")]
     pub created_at: i64,
     #[serde(with = "opt_serde_timestamp")]
@@ -58,26 +367,144 @@ pub struct Invoice {
 }
 
 /// Invoice Status
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum InvoiceStatus {
     New,
+    PendingConfirmation,
+    InFlight,
     Paid,
+    Underpaid,
+    Overpaid,
     Expired,
+    Cancelled,
     Unknown(String),
 }
 
+impl InvoiceStatus {
+    /// Name of the status as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            InvoiceStatus::New => "new",
+            InvoiceStatus::PendingConfirmation => "pending_confirmation",
+            InvoiceStatus::InFlight => "in_flight",
+            InvoiceStatus::Paid => "paid",
+            InvoiceStatus::Underpaid => "underpaid",
+            InvoiceStatus::Overpaid => "overpaid",
+            InvoiceStatus::Expired => "expired",
+            InvoiceStatus::Cancelled => "cancelled",
+            InvoiceStatus::Unknown(status) => status,
+        }
+    }
+
+    /// The invoice is final, polling it can stop
+    ///
+    /// An expired invoice may still be settled by a late payment, see
+    /// [`InvoiceStatus::can_transition_to`].
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            InvoiceStatus::Paid
+                | InvoiceStatus::Overpaid
+                | InvoiceStatus::Expired
+                | InvoiceStatus::Cancelled
+        )
+    }
+
+    /// At least the full amount was received
+    pub fn is_settled(&self) -> bool {
+        matches!(self, InvoiceStatus::Paid | InvoiceStatus::Overpaid)
+    }
+
+    /// The merchant has to act, for example to refund a wrong amount
+    pub fn needs_attention(&self) -> bool {
+        matches!(
+            self,
+            InvoiceStatus::Underpaid | InvoiceStatus::Overpaid | InvoiceStatus::Unknown(_)
+        )
+    }
+
+    /// An invoice in this status can move to `next`
+    ///
+    /// Final statuses never change, not even to an unknown status, except that
+    /// a payment arriving after an invoice expired still settles it. Other
+    /// transitions from or to an unknown status are allowed, as they cannot be
+    /// checked.
+    pub fn can_transition_to(&self, next: &InvoiceStatus) -> bool {
+        use InvoiceStatus::*;
+
+        match (self, next) {
+            (Expired, Paid | Underpaid | Overpaid) => true,
+            (from, _) if from.is_terminal() => false,
+            (Unknown(_), _) | (_, Unknown(_)) => true,
+            (New, New) => false,
+            (New, _) => true,
+            (InFlight, PendingConfirmation | Paid | Underpaid | Overpaid | Expired) => true,
+            (PendingConfirmation, Paid | Underpaid | Overpaid) => true,
+            (Underpaid, InFlight | PendingConfirmation | Paid | Overpaid | Expired) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<&str> for InvoiceStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "new" => InvoiceStatus::New,
+            "pending_confirmation" => InvoiceStatus::PendingConfirmation,
+            "in_flight" => InvoiceStatus::InFlight,
+            "paid" => InvoiceStatus::Paid,
+            "underpaid" => InvoiceStatus::Underpaid,
+            "overpaid" => InvoiceStatus::Overpaid,
+            "expired" => InvoiceStatus::Expired,
+            "cancelled" => InvoiceStatus::Cancelled,
+            _ => InvoiceStatus::Unknown(status.to_string()),
+        }
+    }
+}
+
+impl From<InvoiceStatus> for WebhookEvent {
+    fn from(status: InvoiceStatus) -> Self {
+        match status {
+            InvoiceStatus::New => WebhookEvent::New,
+            InvoiceStatus::PendingConfirmation => WebhookEvent::PendingConfirmation,
+            InvoiceStatus::InFlight => WebhookEvent::InFlight,
+            InvoiceStatus::Paid => WebhookEvent::Paid,
+            InvoiceStatus::Underpaid => WebhookEvent::Underpaid,
+            InvoiceStatus::Overpaid => WebhookEvent::Overpaid,
+            InvoiceStatus::Expired => WebhookEvent::Expired,
+            InvoiceStatus::Cancelled => WebhookEvent::Cancelled,
+            InvoiceStatus::Unknown(status) => WebhookEvent::Unknown(status),
+        }
+    }
+}
+
+impl TryFrom<WebhookEvent> for InvoiceStatus {
+    type Error = NodelessError;
+
+    /// Fails for events unknown to this crate, which do not necessarily
+    /// report an invoice status
+    fn try_from(event: WebhookEvent) -> Result<Self, Self::Error> {
+        match event {
+            WebhookEvent::New => Ok(InvoiceStatus::New),
+            WebhookEvent::PendingConfirmation => Ok(InvoiceStatus::PendingConfirmation),
+            WebhookEvent::InFlight => Ok(InvoiceStatus::InFlight),
+            WebhookEvent::Paid => Ok(InvoiceStatus::Paid),
+            WebhookEvent::Underpaid => Ok(InvoiceStatus::Underpaid),
+            WebhookEvent::Overpaid => Ok(InvoiceStatus::Overpaid),
+            WebhookEvent::Expired => Ok(InvoiceStatus::Expired),
+            WebhookEvent::Cancelled => Ok(InvoiceStatus::Cancelled),
+            WebhookEvent::Unknown(event) => Err(NodelessError::UnknownStatus(event)),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for InvoiceStatus {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         let status_str = String::deserialize(deserializer)?;
-        match status_str.as_str() {
-            "new" => Ok(InvoiceStatus::New),
-            "paid" => Ok(InvoiceStatus::Paid),
-            "expired" => Ok(InvoiceStatus::Expired),
-            _ => Ok(InvoiceStatus::Unknown(status_str)),
-        }
+        Ok(InvoiceStatus::from(status_str.as_str()))
     }
 }
 
@@ -86,12 +513,7 @@ impl Serialize for InvoiceStatus {
     where
         S: Serializer,
     {
-        match self {
-            InvoiceStatus::New => serializer.serialize_str("new"),
-            InvoiceStatus::Paid => serializer.serialize_str("paid"),
-            InvoiceStatus::Expired => serializer.serialize_str("expired"),
-            InvoiceStatus::Unknown(status_str) => serializer.serialize_str(status_str),
-        }
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -122,11 +544,19 @@ impl Nodeless {
     }
 
     /// Create Store Invoice
-    pub async fn create_store_invoice(
+    ///
+    /// Amounts are checked like by [`InvoiceRequestBuilder::build`], round
+    /// them with [`Currency::round`] first to accept more decimal places.
+    pub async fn create_store_invoice<M>(
         &self,
         store_id: &str,
-        invoice: InvoiceRequest,
-    ) -> Result<Invoice, NodelessError> {
+        invoice: InvoiceRequest<M>,
+    ) -> Result<Invoice<M>, NodelessError>
+    where
+        M: Serialize + DeserializeOwned,
+    {
+        invoice.currency.validate_amount(invoice.amount)?;
+
         let url = self
             .base_url
             .join(&format!("api/v1/store/{}/invoice", store_id))?;
@@ -143,6 +573,19 @@ impl Nodeless {
         store_id: &str,
         invoice_id: &str,
     ) -> Result<Invoice, NodelessError> {
+        self.get_store_invoice_with_metadata(store_id, invoice_id)
+            .await
+    }
+
+    /// Get Invoice with metadata of type `M`
+    pub async fn get_store_invoice_with_metadata<M>(
+        &self,
+        store_id: &str,
+        invoice_id: &str,
+    ) -> Result<Invoice<M>, NodelessError>
+    where
+        M: DeserializeOwned,
+    {
         let url = self
             .base_url
             .join(&format!("api/v1/store/{}/invoice/{}", store_id, invoice_id))?;
@@ -165,3 +608,337 @@ impl Nodeless {
         Ok(serde_json::from_value(res["status"].to_owned())?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn builder() -> InvoiceRequestBuilder {
+        InvoiceRequest::builder()
+            .amount(FiatAmount::from_str("21.21").unwrap())
+            .currency(Currency::USD)
+            .buyer_email("hi@nodeless.io")
+            .redirect_url(Url::from_str("https://nodeless.io").unwrap())
+    }
+
+    #[test]
+    fn builds_valid_request() {
+        let request = builder().metadata("order_id", "1042").build().unwrap();
+        assert_eq!(request.currency, Currency::USD);
+        assert_eq!(request.metadata.unwrap()["order_id"], "1042");
+        assert!(builder().build().unwrap().metadata.is_none());
+    }
+
+    #[test]
+    fn email_syntax() {
+        for email in [
+            "hi@nodeless.io",
+            "first.last+tag@sub.example.com",
+            "o'brien@example.co.uk",
+        ] {
+            assert!(is_valid_email(email), "{email}");
+        }
+        for email in [
+            "",
+            "nodeless.io",
+            "@nodeless.io",
+            "hi@",
+            "hi@localhost",
+            ".hi@nodeless.io",
+            "hi.@nodeless.io",
+            "h..i@nodeless.io",
+            "hi@-nodeless.io",
+            "hi@nodeless..io",
+            "hi there@nodeless.io",
+        ] {
+            assert!(!is_valid_email(email), "{email}");
+        }
+        assert!(builder().buyer_email("nodeless.io").build().is_err());
+    }
+
+    #[test]
+    fn amount_precision_of_currency() {
+        let amount = |s: &str| FiatAmount::from_str(s).unwrap();
+        assert!(builder().amount(amount("21.2")).build().is_ok());
+        assert!(builder().amount(amount("21.210")).build().is_ok());
+        assert!(builder().amount(amount("21.211")).build().is_err());
+        assert!(builder().amount(amount("0")).build().is_err());
+
+        let jpy = builder().currency(Currency::JPY);
+        assert!(jpy.clone().amount(amount("2100")).build().is_ok());
+        assert!(jpy.amount(amount("2100.5")).build().is_err());
+
+        let btc = builder().currency(Currency::BTC);
+        assert!(btc.clone().amount(amount("0.00000001")).build().is_ok());
+        assert!(btc.amount(amount("0.000000001")).build().is_err());
+    }
+
+    #[test]
+    fn metadata_limits() {
+        let mut full = builder();
+        for i in 0..MAX_METADATA_KEYS {
+            full = full.metadata(&format!("key{i}"), "value");
+        }
+        assert!(full.clone().build().is_ok());
+        assert!(full.metadata("one_more", "value").build().is_err());
+
+        assert!(builder().metadata("", "value").build().is_err());
+        let key = "k".repeat(MAX_METADATA_KEY_LENGTH);
+        assert!(builder().metadata(&key, "value").build().is_ok());
+        assert!(builder().metadata(&(key + "k"), "value").build().is_err());
+
+        let value = "v".repeat(MAX_METADATA_VALUE_LENGTH);
+        assert!(builder().metadata("key", &value).build().is_ok());
+        assert!(builder().metadata("key", &(value + "v")).build().is_err());
+    }
+
+    #[test]
+    fn metadata_limits_are_configurable() {
+        let value = "v".repeat(MAX_METADATA_VALUE_LENGTH + 1);
+        let limits = MetadataLimits {
+            max_value_length: 1000,
+            ..Default::default()
+        };
+        assert!(builder()
+            .metadata("key", &value)
+            .metadata_limits(limits)
+            .build()
+            .is_ok());
+
+        let strict = InvoiceDefaults {
+            metadata_limits: Some(MetadataLimits {
+                max_keys: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let two_entries = builder().metadata("a", "1").metadata("b", "2");
+        assert!(two_entries
+            .clone()
+            .defaults(strict.clone())
+            .build()
+            .is_err());
+        // Limits set on the builder take precedence over the defaults
+        assert!(two_entries
+            .defaults(strict)
+            .metadata_limits(MetadataLimits::default())
+            .build()
+            .is_ok());
+
+        let request = builder().build().unwrap();
+        assert!(request
+            .clone()
+            .with_metadata(json!({"note": value}))
+            .is_err());
+        assert!(request
+            .with_metadata_within(json!({"note": value}), &limits)
+            .is_ok());
+    }
+
+    #[test]
+    fn defaults_are_merged() {
+        let defaults = InvoiceDefaults {
+            currency: Some(Currency::EUR),
+            redirect_url: Some(Url::from_str("https://shop.example.com").unwrap()),
+            metadata: Metadata::from([
+                ("source".to_string(), "shop".to_string()),
+                ("order_id".to_string(), "default".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let request = InvoiceRequest::builder()
+            .amount(FiatAmount::from_str("5").unwrap())
+            .buyer_email("hi@nodeless.io")
+            .metadata("order_id", "1042")
+            .defaults(defaults.clone())
+            .build()
+            .unwrap();
+        assert_eq!(request.currency, Currency::EUR);
+        assert_eq!(request.redirect_url.as_str(), "https://shop.example.com/");
+        let metadata = request.metadata.unwrap();
+        assert_eq!(metadata["source"], "shop");
+        assert_eq!(metadata["order_id"], "1042");
+
+        let request = builder().defaults(defaults).build().unwrap();
+        assert_eq!(request.currency, Currency::USD);
+        assert_eq!(request.redirect_url.as_str(), "https://nodeless.io/");
+    }
+
+    #[test]
+    fn typed_metadata_is_checked() {
+        let request = builder().build().unwrap();
+        assert!(request
+            .clone()
+            .with_metadata(json!({"order_id": 1042}))
+            .is_ok());
+        assert!(request.clone().with_metadata(json!(["order_id"])).is_err());
+        assert!(request
+            .clone()
+            .with_metadata(json!({"note": "n".repeat(MAX_METADATA_VALUE_LENGTH + 1)}))
+            .is_err());
+        assert!(request
+            .with_metadata(json!({"k".repeat(MAX_METADATA_KEY_LENGTH + 1): "value"}))
+            .is_err());
+    }
+
+    const STATUSES: [InvoiceStatus; 8] = [
+        InvoiceStatus::New,
+        InvoiceStatus::PendingConfirmation,
+        InvoiceStatus::InFlight,
+        InvoiceStatus::Paid,
+        InvoiceStatus::Underpaid,
+        InvoiceStatus::Overpaid,
+        InvoiceStatus::Expired,
+        InvoiceStatus::Cancelled,
+    ];
+
+    #[test]
+    fn invoice_status_round_trips() {
+        for status in STATUSES {
+            let json = format!("\"{}\"", status.as_str());
+            assert_eq!(serde_json::to_string(&status).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<InvoiceStatus>(&json).unwrap(),
+                status
+            );
+            assert_eq!(InvoiceStatus::from(status.as_str()), status);
+        }
+        assert_eq!(
+            InvoiceStatus::from("pending_confirmation"),
+            InvoiceStatus::PendingConfirmation
+        );
+
+        let unknown = InvoiceStatus::Unknown("refunded".to_string());
+        assert_eq!(InvoiceStatus::from("refunded"), unknown);
+        assert_eq!(serde_json::to_string(&unknown).unwrap(), "\"refunded\"");
+        assert_eq!(
+            serde_json::from_str::<InvoiceStatus>("\"refunded\"").unwrap(),
+            unknown
+        );
+        // Names are case sensitive, as sent by the api
+        assert_eq!(
+            InvoiceStatus::from("Paid"),
+            InvoiceStatus::Unknown("Paid".to_string())
+        );
+    }
+
+    #[test]
+    fn invoice_status_converts_to_and_from_events() {
+        for status in STATUSES {
+            let event = WebhookEvent::from(status.clone());
+            assert_eq!(event.as_str(), status.as_str());
+            assert_eq!(InvoiceStatus::try_from(event).unwrap(), status);
+        }
+
+        assert_eq!(
+            WebhookEvent::from(InvoiceStatus::Unknown("refunded".to_string())),
+            WebhookEvent::Unknown("refunded".to_string())
+        );
+        assert!(matches!(
+            InvoiceStatus::try_from(WebhookEvent::Unknown("refunded".to_string())),
+            Err(NodelessError::UnknownStatus(event)) if event == "refunded"
+        ));
+    }
+
+    #[test]
+    fn invoice_status_predicates() {
+        use InvoiceStatus::*;
+
+        let terminal = [Paid, Overpaid, Expired, Cancelled];
+        let settled = [Paid, Overpaid];
+        let attention = [Underpaid, Overpaid];
+        for status in STATUSES {
+            assert_eq!(
+                status.is_terminal(),
+                terminal.contains(&status),
+                "{status:?}"
+            );
+            assert_eq!(status.is_settled(), settled.contains(&status), "{status:?}");
+            assert_eq!(
+                status.needs_attention(),
+                attention.contains(&status),
+                "{status:?}"
+            );
+        }
+
+        let unknown = Unknown("refunded".to_string());
+        assert!(!unknown.is_terminal());
+        assert!(!unknown.is_settled());
+        assert!(unknown.needs_attention());
+    }
+
+    fn unknown() -> InvoiceStatus {
+        InvoiceStatus::Unknown("refunding".to_string())
+    }
+
+    #[test]
+    fn transition_table() {
+        use InvoiceStatus::*;
+
+        let allowed = [
+            (New, PendingConfirmation),
+            (New, InFlight),
+            (New, Paid),
+            (New, Underpaid),
+            (New, Overpaid),
+            (New, Expired),
+            (New, Cancelled),
+            (InFlight, PendingConfirmation),
+            (InFlight, Paid),
+            (InFlight, Expired),
+            (PendingConfirmation, Paid),
+            (PendingConfirmation, Overpaid),
+            (Underpaid, Paid),
+            (Underpaid, Expired),
+            (Expired, Paid),
+            (Expired, Underpaid),
+            (Expired, Overpaid),
+            (New, unknown()),
+            (unknown(), New),
+            (unknown(), Paid),
+        ];
+        for (from, to) in allowed {
+            assert!(from.can_transition_to(&to), "{from:?} -> {to:?}");
+        }
+
+        let rejected = [
+            (New, New),
+            (InFlight, New),
+            (PendingConfirmation, New),
+            (PendingConfirmation, Expired),
+            (Underpaid, New),
+        ];
+        for (from, to) in rejected {
+            assert!(!from.can_transition_to(&to), "{from:?} -> {to:?}");
+        }
+    }
+
+    #[test]
+    fn terminal_statuses_only_accept_late_payments() {
+        use InvoiceStatus::*;
+
+        for from in [Paid, Overpaid, Expired, Cancelled] {
+            for to in [
+                New,
+                PendingConfirmation,
+                InFlight,
+                Paid,
+                Underpaid,
+                Overpaid,
+                Expired,
+                Cancelled,
+                unknown(),
+            ] {
+                let late_payment = from == Expired && matches!(to, Paid | Underpaid | Overpaid);
+                if to != from && !late_payment {
+                    assert!(!from.can_transition_to(&to), "{from:?} -> {to:?}");
+                }
+            }
+        }
+    }
+}
@@ -3,8 +3,9 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::amount::{Decimal, Sats};
 use crate::error::NodelessError;
-use crate::serde_utils::{opt_serde_timestamp, serde_timestamp};
+use crate::serde_utils::{opt_serde_timestamp, serde_decimal, serde_timestamp};
 use crate::Nodeless;
 
 /// Transactable Type
@@ -14,16 +15,32 @@ pub enum TransactableType {
     Other(String),
 }
 
+impl TransactableType {
+    /// Name of the transactable type as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            TransactableType::Donation => "Donation",
+            TransactableType::Other(type_) => type_,
+        }
+    }
+}
+
+impl From<&str> for TransactableType {
+    fn from(type_: &str) -> Self {
+        match type_ {
+            "Donation" => TransactableType::Donation,
+            _ => TransactableType::Other(type_.to_string()),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for TransactableType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let status_str = String::deserialize(deserializer)?;
-        match status_str.as_str() {
-            "Donation" => Ok(TransactableType::Donation),
-            _ => Ok(TransactableType::Other(status_str)),
-        }
+        let type_ = String::deserialize(deserializer)?;
+        Ok(TransactableType::from(type_.as_str()))
     }
 }
 
@@ -32,10 +49,7 @@ impl Serialize for TransactableType {
     where
         S: Serializer,
     {
-        match self {
-            TransactableType::Donation => serializer.serialize_str("Donation"),
-            TransactableType::Other(status_str) => serializer.serialize_str(status_str),
-        }
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -46,16 +60,32 @@ pub enum TransactionStatus {
     Other(String),
 }
 
+impl TransactionStatus {
+    /// Name of the status as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            TransactionStatus::Settled => "settled",
+            TransactionStatus::Other(status) => status,
+        }
+    }
+}
+
+impl From<&str> for TransactionStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "settled" => TransactionStatus::Settled,
+            _ => TransactionStatus::Other(status.to_string()),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for TransactionStatus {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let status_str = String::deserialize(deserializer)?;
-        match status_str.as_str() {
-            "settled" => Ok(TransactionStatus::Settled),
-            _ => Ok(TransactionStatus::Other(status_str)),
-        }
+        let status = String::deserialize(deserializer)?;
+        Ok(TransactionStatus::from(status.as_str()))
     }
 }
 
@@ -64,10 +94,7 @@ impl Serialize for TransactionStatus {
     where
         S: Serializer,
     {
-        match self {
-            TransactionStatus::Settled => serializer.serialize_str("settled"),
-            TransactionStatus::Other(status_str) => serializer.serialize_str(status_str),
-        }
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -77,8 +104,8 @@ pub struct Transactable {
     id: u64,
     uuid: String,
     donation_page_id: Option<u64>,
-    amount: u64,
-    amount_paid: u64,
+    amount: Sats,
+    amount_paid: Sats,
     name: Option<String>,
     message: Option<String>,
     status: String,
@@ -99,7 +126,10 @@ pub struct Transaction {
     pub id: String,
     pub transactable_type: TransactableType,
     pub transactable: Transactable,
-    pub amount: f64,
+    /// Signed decimal rather than [`FiatAmount`](crate::amount::FiatAmount),
+    /// which rejects negative values, so debits such as fees can be represented
+    #[serde(with = "serde_decimal")]
+    pub amount: Decimal,
     #[serde(rename = "type")]
     pub type_: String,
     pub status: TransactionStatus,
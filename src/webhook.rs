@@ -1,51 +1,211 @@
 //! Webhook Types
 
-use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sha2::Sha256;
 use url::Url;
 
+use crate::amount::Sats;
+use crate::error::NodelessError;
 use crate::serde_utils::{opt_serde_timestamp, opt_serde_url, serde_url};
+use crate::Nodeless;
+
+type HmacSha256 = Hmac<Sha256>;
 
 /// Event type where webhook is triggered
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum WebhookEvent {
-    #[serde(rename = "new")]
     New,
-    #[serde(rename = "pending_confirmation")]
     PendingConfirmation,
-    #[serde(rename = "paid")]
     Paid,
-    #[serde(rename = "expired")]
     Expired,
-    #[serde(rename = "cancelled")]
     Cancelled,
-    #[serde(rename = "underpaid")]
     Underpaid,
-    #[serde(rename = "overpaid")]
     Overpaid,
-    #[serde(rename = "in_flight")]
     InFlight,
+    Unknown(String),
+}
+
+impl WebhookEvent {
+    /// Every event of an invoice's lifecycle
+    pub fn invoice_lifecycle() -> Vec<WebhookEvent> {
+        vec![
+            WebhookEvent::New,
+            WebhookEvent::PendingConfirmation,
+            WebhookEvent::InFlight,
+            WebhookEvent::Paid,
+            WebhookEvent::Underpaid,
+            WebhookEvent::Overpaid,
+            WebhookEvent::Expired,
+            WebhookEvent::Cancelled,
+        ]
+    }
+
+    /// Events reporting a received payment
+    pub fn payments() -> Vec<WebhookEvent> {
+        vec![
+            WebhookEvent::Paid,
+            WebhookEvent::Underpaid,
+            WebhookEvent::Overpaid,
+        ]
+    }
+
+    /// Name of the event as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            WebhookEvent::New => "new",
+            WebhookEvent::PendingConfirmation => "pending_confirmation",
+            WebhookEvent::Paid => "paid",
+            WebhookEvent::Expired => "expired",
+            WebhookEvent::Cancelled => "cancelled",
+            WebhookEvent::Underpaid => "underpaid",
+            WebhookEvent::Overpaid => "overpaid",
+            WebhookEvent::InFlight => "in_flight",
+            WebhookEvent::Unknown(event) => event,
+        }
+    }
+}
+
+impl From<&str> for WebhookEvent {
+    fn from(event: &str) -> Self {
+        match event {
+            "new" => WebhookEvent::New,
+            "pending_confirmation" => WebhookEvent::PendingConfirmation,
+            "paid" => WebhookEvent::Paid,
+            "expired" => WebhookEvent::Expired,
+            "cancelled" => WebhookEvent::Cancelled,
+            "underpaid" => WebhookEvent::Underpaid,
+            "overpaid" => WebhookEvent::Overpaid,
+            "in_flight" => WebhookEvent::InFlight,
+            _ => WebhookEvent::Unknown(event.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for WebhookEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let event = String::deserialize(deserializer)?;
+        Ok(WebhookEvent::from(event.as_str()))
+    }
+}
+
+impl Serialize for WebhookEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
 }
 
 /// Type of webhook
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum WebHookType {
-    #[serde(rename = "store")]
     Store,
-    #[serde(rename = "donation_page")]
     DonationPage,
-    #[serde(rename = "paywall")]
     Paywall,
-    #[serde(rename = "inbox")]
     Inbox,
+    Unknown(String),
+}
+
+impl WebHookType {
+    /// Name of the webhook type as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            WebHookType::Store => "store",
+            WebHookType::DonationPage => "donation_page",
+            WebHookType::Paywall => "paywall",
+            WebHookType::Inbox => "inbox",
+            WebHookType::Unknown(type_) => type_,
+        }
+    }
+}
+
+impl From<&str> for WebHookType {
+    fn from(type_: &str) -> Self {
+        match type_ {
+            "store" => WebHookType::Store,
+            "donation_page" => WebHookType::DonationPage,
+            "paywall" => WebHookType::Paywall,
+            "inbox" => WebHookType::Inbox,
+            _ => WebHookType::Unknown(type_.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for WebHookType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let type_ = String::deserialize(deserializer)?;
+        Ok(WebHookType::from(type_.as_str()))
+    }
+}
+
+impl Serialize for WebHookType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
 }
 
 /// Status of webhook
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum WebhookStatus {
-    #[serde(rename = "active")]
     Active,
-    #[serde(rename = "inactive")]
     Inactive,
+    Unknown(String),
+}
+
+impl WebhookStatus {
+    /// Name of the status as used by the api
+    pub fn as_str(&self) -> &str {
+        match self {
+            WebhookStatus::Active => "active",
+            WebhookStatus::Inactive => "inactive",
+            WebhookStatus::Unknown(status) => status,
+        }
+    }
+}
+
+impl From<&str> for WebhookStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "active" => WebhookStatus::Active,
+            "inactive" => WebhookStatus::Inactive,
+            _ => WebhookStatus::Unknown(status.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for WebhookStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let status = String::deserialize(deserializer)?;
+        Ok(WebhookStatus::from(status.as_str()))
+    }
+}
+
+impl Serialize for WebhookStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
 }
 
 /// Create Webhook Information
@@ -60,6 +220,184 @@ pub struct CreateWebhook {
     pub status: WebhookStatus,
 }
 
+impl CreateWebhook {
+    /// Create validating builder
+    pub fn builder() -> CreateWebhookBuilder {
+        CreateWebhookBuilder::default()
+    }
+}
+
+/// Minimum length of a webhook secret accepted by [`CreateWebhookBuilder`]
+pub const MIN_SECRET_LENGTH: usize = 24;
+
+/// Minimum number of distinct characters of a webhook secret accepted by
+/// [`CreateWebhookBuilder`]
+pub const MIN_SECRET_DISTINCT_CHARS: usize = 10;
+
+/// Create Webhook Builder
+///
+/// # Example
+/// ```
+/// use std::str::FromStr;
+///
+/// use nodeless_rs::webhook::{CreateWebhook, WebHookType};
+/// use url::Url;
+///
+/// let webhook = CreateWebhook::builder()
+///     .type_(WebHookType::Store)
+///     .url(Url::from_str("https://example.com/webhook").unwrap())
+///     .invoice_lifecycle_events()
+///     .generate_secret()
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CreateWebhookBuilder {
+    type_: Option<WebHookType>,
+    url: Option<Url>,
+    events: Vec<WebhookEvent>,
+    secret: Option<String>,
+    status: Option<WebhookStatus>,
+    dev_mode: bool,
+}
+
+impl CreateWebhookBuilder {
+    /// Set type of webhook
+    pub fn type_(mut self, type_: WebHookType) -> Self {
+        self.type_ = Some(type_);
+        self
+    }
+
+    /// Set url deliveries are sent to
+    pub fn url(mut self, url: Url) -> Self {
+        self.url = Some(url);
+        self
+    }
+
+    /// Add event triggering the webhook
+    pub fn event(mut self, event: WebhookEvent) -> Self {
+        if !self.events.contains(&event) {
+            self.events.push(event);
+        }
+        self
+    }
+
+    /// Add events triggering the webhook
+    pub fn events<I: IntoIterator<Item = WebhookEvent>>(self, events: I) -> Self {
+        events
+            .into_iter()
+            .fold(self, |builder, event| builder.event(event))
+    }
+
+    /// Add every event of an invoice's lifecycle
+    pub fn invoice_lifecycle_events(self) -> Self {
+        self.events(WebhookEvent::invoice_lifecycle())
+    }
+
+    /// Add events reporting a received payment
+    pub fn payment_events(self) -> Self {
+        self.events(WebhookEvent::payments())
+    }
+
+    /// Set secret deliveries are signed with
+    pub fn secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    /// Set a randomly generated secret
+    ///
+    /// Read it back from the built [`CreateWebhook`] to verify deliveries.
+    pub fn generate_secret(mut self) -> Self {
+        self.secret = Some(crate::webhook_secret::generate_secret());
+        self
+    }
+
+    /// Set status, defaults to [`WebhookStatus::Active`]
+    pub fn status(mut self, status: WebhookStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Allow `http` and local urls for development
+    pub fn dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    /// Validate and build [`CreateWebhook`]
+    pub fn build(self) -> Result<CreateWebhook, NodelessError> {
+        let type_ = self
+            .type_
+            .ok_or_else(|| invalid_webhook("type is required"))?;
+        let url = self.url.ok_or_else(|| invalid_webhook("url is required"))?;
+        let secret = self
+            .secret
+            .ok_or_else(|| invalid_webhook("secret is required"))?;
+
+        if !self.dev_mode {
+            if url.scheme() != "https" {
+                return Err(invalid_webhook("url must use https"));
+            }
+            if is_local_host(&url) {
+                return Err(invalid_webhook("url must not point to a local host"));
+            }
+        } else if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid_webhook("url must use http or https"));
+        }
+
+        if self.events.is_empty() {
+            return Err(invalid_webhook("at least one event is required"));
+        }
+
+        let distinct_chars = secret
+            .chars()
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        if secret.chars().count() < MIN_SECRET_LENGTH || distinct_chars < MIN_SECRET_DISTINCT_CHARS
+        {
+            return Err(invalid_webhook(&format!(
+                "secret must have at least {MIN_SECRET_LENGTH} characters and {MIN_SECRET_DISTINCT_CHARS} distinct ones"
+            )));
+        }
+
+        Ok(CreateWebhook {
+            type_,
+            url,
+            events: self.events,
+            secret,
+            status: self.status.unwrap_or(WebhookStatus::Active),
+        })
+    }
+}
+
+fn invalid_webhook(reason: &str) -> NodelessError {
+    NodelessError::InvalidWebhook(reason.to_string())
+}
+
+fn is_local_host(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            domain == "localhost" || domain.ends_with(".localhost") || domain.ends_with(".local")
+        }
+        Some(url::Host::Ipv4(ip)) => is_local_ipv4(ip),
+        Some(url::Host::Ipv6(ip)) => {
+            let segment = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local fc00::/7 and link local fe80::/10
+                || segment & 0xfe00 == 0xfc00
+                || segment & 0xffc0 == 0xfe80
+                || ip.to_ipv4_mapped().is_some_and(is_local_ipv4)
+        }
+        None => true,
+    }
+}
+
+fn is_local_ipv4(ip: std::net::Ipv4Addr) -> bool {
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+}
+
 /// Webhook
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Webhook {
@@ -76,3 +414,505 @@ pub struct Webhook {
     #[serde(rename = "lastDeliveryAt")]
     pub last_delivery_at: Option<i64>,
 }
+
+/// Header carrying the HMAC-SHA256 signature of a webhook delivery
+pub const SIGNATURE_HEADER: &str = "nodeless-signature";
+
+/// Webhook Payload
+///
+/// Body of a webhook delivery sent by Nodeless
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPayload {
+    pub uuid: String,
+    pub status: WebhookEvent,
+    pub sats_amount: Option<Sats>,
+    pub metadata: Option<Value>,
+    #[serde(default, with = "opt_serde_timestamp")]
+    pub created_at: Option<i64>,
+    #[serde(default, with = "opt_serde_timestamp")]
+    pub paid_at: Option<i64>,
+}
+
+/// Verified webhook delivery
+#[derive(Clone, Debug)]
+pub struct WebhookDelivery {
+    pub type_: WebHookType,
+    pub payload: WebhookPayload,
+    /// Raw json body of the delivery
+    pub raw: Value,
+}
+
+impl WebhookDelivery {
+    /// Build delivery from a raw json body
+    pub fn from_raw(type_: WebHookType, raw: Value) -> Result<Self, NodelessError> {
+        Ok(Self {
+            type_,
+            payload: serde_json::from_value(raw.clone())?,
+            raw,
+        })
+    }
+
+    /// Event that triggered the delivery
+    pub fn event(&self) -> &WebhookEvent {
+        &self.payload.status
+    }
+
+    /// Deserialize the raw body into a typed payload
+    pub fn payload_as<T: DeserializeOwned>(&self) -> Result<T, NodelessError> {
+        Ok(serde_json::from_value(self.raw.clone())?)
+    }
+}
+
+/// Webhook Verifier
+///
+/// Checks the signature of incoming deliveries for a webhook
+#[derive(Clone, Debug)]
+pub struct WebhookVerifier {
+    type_: WebHookType,
+    secret: String,
+    /// Secret replaced by a rotation and the timestamp until it is accepted
+    previous: Option<(String, i64)>,
+}
+
+impl WebhookVerifier {
+    /// Create verifier
+    /// # Arguments
+    /// * `type_` - Type of the webhook the deliveries are sent for
+    /// * `secret` - Secret of the webhook
+    pub fn new(type_: WebHookType, secret: &str) -> Self {
+        Self {
+            type_,
+            secret: secret.to_string(),
+            previous: None,
+        }
+    }
+
+    /// Also accept `secret` until the unix timestamp `valid_until`
+    ///
+    /// Use this to restore the overlap window of a rotation after a restart.
+    pub fn with_previous_secret(mut self, secret: &str, valid_until: i64) -> Self {
+        self.previous = Some((secret.to_string(), valid_until));
+        self
+    }
+
+    /// Switch to `new_secret`, accepting the current secret for `overlap`
+    ///
+    /// Rotate the verifier before updating the webhook so deliveries signed
+    /// with either secret verify while the change propagates.
+    pub fn rotate(&mut self, new_secret: &str, overlap: Duration) {
+        self.rotate_at(new_secret, overlap, chrono::Utc::now().timestamp());
+    }
+
+    /// Switch to `new_secret` at the unix timestamp `now`, accepting the
+    /// current secret for `overlap`
+    pub fn rotate_at(&mut self, new_secret: &str, overlap: Duration, now: i64) {
+        let overlap = i64::try_from(overlap.as_secs()).unwrap_or(i64::MAX);
+        let valid_until = now.saturating_add(overlap);
+        let previous = std::mem::replace(&mut self.secret, new_secret.to_string());
+        self.previous = Some((previous, valid_until));
+    }
+
+    /// Hex encoded signature of `body`
+    pub fn sign(&self, body: &[u8]) -> String {
+        sign_payload(&self.secret, body)
+    }
+
+    /// Verify `signature` of `body` and parse the delivery
+    /// # Arguments
+    /// * `body` - Raw request body
+    /// * `signature` - Value of the [`SIGNATURE_HEADER`] header
+    pub fn verify(&self, body: &[u8], signature: &str) -> Result<WebhookDelivery, NodelessError> {
+        self.verify_at(body, signature, chrono::Utc::now().timestamp())
+    }
+
+    /// Verify `signature` of `body` at the unix timestamp `now`
+    ///
+    /// The previous secret of a rotation is only accepted until the end of
+    /// its overlap window.
+    pub fn verify_at(
+        &self,
+        body: &[u8],
+        signature: &str,
+        now: i64,
+    ) -> Result<WebhookDelivery, NodelessError> {
+        let previous_valid = self.previous.as_ref().is_some_and(|(secret, valid_until)| {
+            now <= *valid_until && verify_signature(secret, body, signature)
+        });
+        if !verify_signature(&self.secret, body, signature) && !previous_valid {
+            return Err(NodelessError::InvalidSignature);
+        }
+
+        WebhookDelivery::from_raw(self.type_.clone(), serde_json::from_slice(body)?)
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `body` keyed with `secret`
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature.trim()) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Owner of a webhook
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WebhookTarget {
+    Store(String),
+    Paywall(String),
+}
+
+impl WebhookTarget {
+    /// Type of the webhooks owned by the target
+    pub fn webhook_type(&self) -> WebHookType {
+        match self {
+            WebhookTarget::Store(_) => WebHookType::Store,
+            WebhookTarget::Paywall(_) => WebHookType::Paywall,
+        }
+    }
+
+    /// Id of the store or paywall
+    pub fn id(&self) -> &str {
+        match self {
+            WebhookTarget::Store(id) | WebhookTarget::Paywall(id) => id,
+        }
+    }
+}
+
+impl fmt::Display for WebhookTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.webhook_type().as_str(), self.id())
+    }
+}
+
+impl Nodeless {
+    /// Get Webhooks of a store or paywall
+    pub async fn get_webhooks(
+        &self,
+        target: &WebhookTarget,
+    ) -> Result<Vec<Webhook>, NodelessError> {
+        match target {
+            WebhookTarget::Store(id) => self.get_store_webhooks(id).await,
+            WebhookTarget::Paywall(id) => self.get_paywall_webhooks(id).await,
+        }
+    }
+
+    /// Get Webhook of a store or paywall
+    pub async fn get_webhook(
+        &self,
+        target: &WebhookTarget,
+        webhook_id: &str,
+    ) -> Result<Webhook, NodelessError> {
+        match target {
+            WebhookTarget::Store(id) => self.get_store_webhook(id, webhook_id).await,
+            WebhookTarget::Paywall(id) => self.get_paywall_webhook(id, webhook_id).await,
+        }
+    }
+
+    /// Create Webhook for a store or paywall
+    pub async fn create_webhook(
+        &self,
+        target: &WebhookTarget,
+        webhook: CreateWebhook,
+    ) -> Result<Webhook, NodelessError> {
+        match target {
+            WebhookTarget::Store(id) => self.create_store_webhook(id, webhook).await,
+            WebhookTarget::Paywall(id) => self.create_paywall_webhook(id, webhook).await,
+        }
+    }
+
+    /// Update Webhook of a store or paywall
+    pub async fn update_webhook(
+        &self,
+        target: &WebhookTarget,
+        webhook_id: &str,
+        webhook: CreateWebhook,
+    ) -> Result<Webhook, NodelessError> {
+        match target {
+            WebhookTarget::Store(id) => self.update_store_webhook(id, webhook_id, webhook).await,
+            WebhookTarget::Paywall(id) => {
+                self.update_paywall_webhook(id, webhook_id, webhook).await
+            }
+        }
+    }
+
+    /// Delete Webhook of a store or paywall
+    pub async fn delete_webhook(
+        &self,
+        target: &WebhookTarget,
+        webhook_id: &str,
+    ) -> Result<(), NodelessError> {
+        match target {
+            WebhookTarget::Store(id) => self.delete_store_webhook(id, webhook_id).await,
+            WebhookTarget::Paywall(id) => self.delete_paywall_webhook(id, webhook_id).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T>(value: T, json: &str)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(serde_json::to_string(&value).unwrap(), json);
+        assert_eq!(serde_json::from_str::<T>(json).unwrap(), value);
+    }
+
+    #[test]
+    fn webhook_event_round_trips() {
+        for event in WebhookEvent::invoice_lifecycle() {
+            round_trip(event.clone(), &format!("\"{}\"", event.as_str()));
+        }
+        round_trip(
+            WebhookEvent::PendingConfirmation,
+            "\"pending_confirmation\"",
+        );
+        round_trip(
+            WebhookEvent::Unknown("refunded".to_string()),
+            "\"refunded\"",
+        );
+    }
+
+    #[test]
+    fn webhook_type_round_trips() {
+        round_trip(WebHookType::Store, "\"store\"");
+        round_trip(WebHookType::DonationPage, "\"donation_page\"");
+        round_trip(WebHookType::Paywall, "\"paywall\"");
+        round_trip(WebHookType::Inbox, "\"inbox\"");
+        round_trip(WebHookType::Unknown("shop".to_string()), "\"shop\"");
+    }
+
+    #[test]
+    fn webhook_status_round_trips() {
+        round_trip(WebhookStatus::Active, "\"active\"");
+        round_trip(WebhookStatus::Inactive, "\"inactive\"");
+        round_trip(WebhookStatus::Unknown("paused".to_string()), "\"paused\"");
+    }
+
+    fn builder(url: &str) -> CreateWebhookBuilder {
+        CreateWebhook::builder()
+            .type_(WebHookType::Store)
+            .url(Url::parse(url).unwrap())
+            .event(WebhookEvent::Paid)
+            .generate_secret()
+    }
+
+    fn rejection(builder: CreateWebhookBuilder) -> String {
+        match builder.build() {
+            Err(NodelessError::InvalidWebhook(reason)) => reason,
+            other => panic!("expected invalid webhook, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn builder_requires_https() {
+        assert!(builder("https://example.com/hook").build().is_ok());
+        assert_eq!(
+            rejection(builder("http://example.com/hook")),
+            "url must use https"
+        );
+        assert!(builder("http://example.com/hook")
+            .dev_mode(true)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn builder_rejects_local_hosts() {
+        for url in [
+            "https://localhost/hook",
+            "https://shop.localhost/hook",
+            "https://printer.local/hook",
+            "https://127.0.0.1/hook",
+            "https://10.0.0.8/hook",
+            "https://192.168.1.2/hook",
+            "https://169.254.169.254/hook",
+            "https://0.0.0.0/hook",
+            "https://[::1]/hook",
+            "https://[::]/hook",
+            "https://[fc00::1]/hook",
+            "https://[fd12:3456::1]/hook",
+            "https://[fe80::1]/hook",
+            "https://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert_eq!(
+                rejection(builder(url)),
+                "url must not point to a local host",
+                "{url}"
+            );
+            assert!(builder(url).dev_mode(true).build().is_ok(), "{url}");
+        }
+
+        for url in [
+            "https://93.184.216.34/hook",
+            "https://[2606:2800:220:1::1]/hook",
+        ] {
+            assert!(builder(url).build().is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn builder_requires_events() {
+        let builder = CreateWebhook::builder()
+            .type_(WebHookType::Store)
+            .url(Url::parse("https://example.com/hook").unwrap())
+            .generate_secret();
+        assert_eq!(rejection(builder), "at least one event is required");
+    }
+
+    #[test]
+    fn builder_presets_add_distinct_events() {
+        let webhook = builder("https://example.com/hook")
+            .payment_events()
+            .invoice_lifecycle_events()
+            .build()
+            .unwrap();
+        // Paid was added first, the presets only append missing events
+        assert_eq!(webhook.events[0], WebhookEvent::Paid);
+        assert_eq!(
+            webhook.events.len(),
+            WebhookEvent::invoice_lifecycle().len()
+        );
+        for event in WebhookEvent::invoice_lifecycle() {
+            assert!(webhook.events.contains(&event), "{event:?}");
+        }
+
+        let webhook = CreateWebhook::builder()
+            .type_(WebHookType::Store)
+            .url(Url::parse("https://example.com/hook").unwrap())
+            .payment_events()
+            .generate_secret()
+            .build()
+            .unwrap();
+        assert_eq!(webhook.events, WebhookEvent::payments());
+        assert_eq!(webhook.status, WebhookStatus::Active);
+    }
+
+    #[test]
+    fn builder_rejects_weak_secrets() {
+        assert!(
+            rejection(builder("https://example.com/hook").secret("secret"))
+                .starts_with("secret must have")
+        );
+        assert!(
+            rejection(builder("https://example.com/hook").secret(&"ab".repeat(20)))
+                .starts_with("secret must have")
+        );
+    }
+
+    const BODY: &[u8] = br#"{"uuid":"inv-1","status":"paid"}"#;
+
+    #[test]
+    fn verify_accepts_valid_signature() {
+        let verifier = WebhookVerifier::new(WebHookType::Store, "secret");
+        let delivery = verifier.verify(BODY, &verifier.sign(BODY)).unwrap();
+
+        assert_eq!(delivery.type_, WebHookType::Store);
+        assert_eq!(delivery.event(), &WebhookEvent::Paid);
+        assert_eq!(delivery.raw["uuid"], "inv-1");
+        assert!(verify_signature(
+            "secret",
+            BODY,
+            &sign_payload("secret", BODY)
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_body() {
+        let verifier = WebhookVerifier::new(WebHookType::Store, "secret");
+        let signature = verifier.sign(BODY);
+        let tampered = br#"{"uuid":"inv-1","status":"expired"}"#;
+
+        assert!(matches!(
+            verifier.verify(tampered, &signature),
+            Err(NodelessError::InvalidSignature)
+        ));
+        assert!(!verify_signature("secret", tampered, &signature));
+    }
+
+    #[test]
+    fn verify_rejects_wrong_secret() {
+        let verifier = WebhookVerifier::new(WebHookType::Store, "secret");
+        let signature = sign_payload("other", BODY);
+
+        assert!(matches!(
+            verifier.verify(BODY, &signature),
+            Err(NodelessError::InvalidSignature)
+        ));
+        assert!(!verify_signature("secret", BODY, &signature));
+    }
+
+    #[test]
+    fn verify_rejects_malformed_signature() {
+        let verifier = WebhookVerifier::new(WebHookType::Store, "secret");
+        let signature = verifier.sign(BODY);
+
+        for malformed in ["", "not hex", "zz", &signature[..10], &signature[1..]] {
+            assert!(
+                matches!(
+                    verifier.verify(BODY, malformed),
+                    Err(NodelessError::InvalidSignature)
+                ),
+                "{malformed:?}"
+            );
+            assert!(!verify_signature("secret", BODY, malformed));
+        }
+    }
+
+    #[test]
+    fn verify_trims_signature_whitespace() {
+        let verifier = WebhookVerifier::new(WebHookType::Store, "secret");
+        let signature = format!(" {}\n", verifier.sign(BODY));
+
+        assert!(verifier.verify(BODY, &signature).is_ok());
+    }
+
+    #[test]
+    fn previous_secret_is_accepted_during_overlap() {
+        let mut verifier = WebhookVerifier::new(WebHookType::Store, "old");
+        verifier.rotate_at("new", Duration::from_secs(60), 1_000);
+        let old_signature = sign_payload("old", BODY);
+
+        assert!(verifier
+            .verify_at(BODY, &verifier.sign(BODY), 5_000)
+            .is_ok());
+        assert!(verifier.verify_at(BODY, &old_signature, 1_000).is_ok());
+        assert!(verifier.verify_at(BODY, &old_signature, 1_060).is_ok());
+        assert!(matches!(
+            verifier.verify_at(BODY, &old_signature, 1_061),
+            Err(NodelessError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn restored_previous_secret_expires() {
+        let verifier =
+            WebhookVerifier::new(WebHookType::Store, "new").with_previous_secret("old", 1_060);
+        let old_signature = sign_payload("old", BODY);
+
+        assert!(verifier.verify_at(BODY, &old_signature, 1_060).is_ok());
+        assert!(verifier.verify_at(BODY, &old_signature, 1_061).is_err());
+    }
+
+    #[test]
+    fn rotate_saturates_long_overlap() {
+        let mut verifier = WebhookVerifier::new(WebHookType::Store, "old");
+        verifier.rotate_at("new", Duration::MAX, 1_000);
+
+        assert!(verifier
+            .verify_at(BODY, &sign_payload("old", BODY), i64::MAX)
+            .is_ok());
+    }
+}
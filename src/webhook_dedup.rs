@@ -0,0 +1,567 @@
+//! Webhook Deduplication
+//!
+//! Nodeless may deliver the same event more than once and a captured delivery
+//! can be replayed. [`WebhookDeduplicator`] claims every delivery it hands to
+//! the [`WebhookRouter`] in a [`SeenStore`] and drops deliveries already
+//! handled within the retention window.
+//!
+//! A claim is only a short lease while the handler runs. It is marked done
+//! once the handler succeeds and released when it fails, so a delivery whose
+//! handler crashed the process is processed again after the lease runs out.
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lru::LruCache;
+
+use crate::error::NodelessError;
+use crate::webhook::WebhookDelivery;
+use crate::webhook_router::WebhookRouter;
+
+/// How long a claimed delivery blocks redeliveries while its handler runs
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// State of a delivery key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeenState {
+    /// Key was not recorded, or its entry expired
+    Unseen,
+    /// Key is claimed and its lease has not run out
+    InProgress,
+    /// Key was handled within the retention window
+    Done,
+}
+
+/// Storage of seen delivery keys
+pub trait SeenStore: Send + Sync {
+    /// Claim `key` until `lease_until`
+    ///
+    /// Entries expired at `now` count as unseen and are replaced. Returns
+    /// [`SeenState::Unseen`] if the key was claimed, the state of the existing
+    /// entry otherwise.
+    fn claim(&self, key: &str, now: i64, lease_until: i64) -> Result<SeenState, NodelessError>;
+
+    /// Mark `key` as done until `expires_at`
+    fn complete(&self, key: &str, expires_at: i64) -> Result<(), NodelessError>;
+
+    /// Forget `key`
+    fn remove(&self, key: &str) -> Result<(), NodelessError>;
+
+    /// Remove entries expired at `now`
+    fn purge(&self, now: i64) -> Result<(), NodelessError>;
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SeenEntry {
+    expires_at: i64,
+    done: bool,
+}
+
+/// In memory [`SeenStore`] holding a bounded number of keys
+///
+/// Expired entries make room for new keys. A store filled with entries that
+/// have not expired refuses new claims with [`NodelessError::SeenStoreFull`]
+/// instead of forgetting handled deliveries, size it for the deliveries
+/// expected within the retention window or use `SqliteSeenStore`.
+#[derive(Debug)]
+pub struct MemorySeenStore {
+    cache: Mutex<LruCache<String, SeenEntry>>,
+}
+
+impl MemorySeenStore {
+    /// Create store holding at most `capacity` keys
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+/// Insert `entry`, dropping entries expired at `now` if the cache is full
+///
+/// Fails rather than evicting an entry that has not expired.
+fn insert(
+    cache: &mut LruCache<String, SeenEntry>,
+    key: &str,
+    entry: SeenEntry,
+    now: i64,
+) -> Result<(), NodelessError> {
+    if !cache.contains(key) && cache.len() == cache.cap().get() {
+        remove_expired(cache, now);
+        if cache.len() == cache.cap().get() {
+            return Err(NodelessError::SeenStoreFull(cache.cap().get()));
+        }
+    }
+    cache.put(key.to_string(), entry);
+    Ok(())
+}
+
+fn remove_expired(cache: &mut LruCache<String, SeenEntry>, now: i64) {
+    let expired: Vec<String> = cache
+        .iter()
+        .filter(|(_, entry)| entry.expires_at <= now)
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in expired {
+        cache.pop(&key);
+    }
+}
+
+impl SeenStore for MemorySeenStore {
+    fn claim(&self, key: &str, now: i64, lease_until: i64) -> Result<SeenState, NodelessError> {
+        let mut cache = self.cache.lock().expect("seen store lock poisoned");
+        match cache.get(key) {
+            Some(entry) if entry.expires_at > now && entry.done => Ok(SeenState::Done),
+            Some(entry) if entry.expires_at > now => Ok(SeenState::InProgress),
+            _ => {
+                let entry = SeenEntry {
+                    expires_at: lease_until,
+                    done: false,
+                };
+                insert(&mut cache, key, entry, now)?;
+                Ok(SeenState::Unseen)
+            }
+        }
+    }
+
+    fn complete(&self, key: &str, expires_at: i64) -> Result<(), NodelessError> {
+        let mut cache = self.cache.lock().expect("seen store lock poisoned");
+        let entry = SeenEntry {
+            expires_at,
+            done: true,
+        };
+        insert(&mut cache, key, entry, chrono::Utc::now().timestamp())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), NodelessError> {
+        self.cache
+            .lock()
+            .expect("seen store lock poisoned")
+            .pop(key);
+        Ok(())
+    }
+
+    fn purge(&self, now: i64) -> Result<(), NodelessError> {
+        remove_expired(
+            &mut self.cache.lock().expect("seen store lock poisoned"),
+            now,
+        );
+        Ok(())
+    }
+}
+
+/// SQLite backed [`SeenStore`]
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteSeenStore {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSeenStore {
+    /// Open store at `path`, creating the table if needed
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, NodelessError> {
+        Self::from_connection(rusqlite::Connection::open(path)?)
+    }
+
+    /// Create store on an existing connection
+    pub fn from_connection(conn: rusqlite::Connection) -> Result<Self, NodelessError> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS seen_webhook_keys (
+                key TEXT PRIMARY KEY,
+                expires_at INTEGER NOT NULL,
+                done INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SeenStore for SqliteSeenStore {
+    fn claim(&self, key: &str, now: i64, lease_until: i64) -> Result<SeenState, NodelessError> {
+        let conn = self.conn.lock().expect("seen store lock poisoned");
+        conn.execute(
+            "DELETE FROM seen_webhook_keys WHERE key = ?1 AND expires_at <= ?2",
+            rusqlite::params![key, now],
+        )?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO seen_webhook_keys (key, expires_at) VALUES (?1, ?2)",
+            rusqlite::params![key, lease_until],
+        )?;
+        if inserted == 1 {
+            return Ok(SeenState::Unseen);
+        }
+
+        let done: bool = conn.query_row(
+            "SELECT done FROM seen_webhook_keys WHERE key = ?1",
+            rusqlite::params![key],
+            |row| row.get(0),
+        )?;
+        Ok(if done {
+            SeenState::Done
+        } else {
+            SeenState::InProgress
+        })
+    }
+
+    fn complete(&self, key: &str, expires_at: i64) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("seen store lock poisoned");
+        conn.execute(
+            "INSERT OR REPLACE INTO seen_webhook_keys (key, expires_at, done) VALUES (?1, ?2, 1)",
+            rusqlite::params![key, expires_at],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("seen store lock poisoned");
+        conn.execute(
+            "DELETE FROM seen_webhook_keys WHERE key = ?1",
+            rusqlite::params![key],
+        )?;
+        Ok(())
+    }
+
+    fn purge(&self, now: i64) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("seen store lock poisoned");
+        conn.execute(
+            "DELETE FROM seen_webhook_keys WHERE expires_at <= ?1",
+            rusqlite::params![now],
+        )?;
+        Ok(())
+    }
+}
+
+/// Outcome of a deduplicated dispatch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DedupOutcome {
+    /// Delivery was handled by a route or the fallback
+    Handled,
+    /// No route or fallback matched the delivery
+    Unhandled,
+    /// Delivery was already handled within the retention window
+    Duplicate,
+    /// Delivery is claimed by another dispatch whose lease has not run out
+    ///
+    /// Retry it later, the claim is released when that handler fails or its
+    /// lease expires.
+    InProgress,
+}
+
+/// Called with the key and the error when a failed delivery cannot be released
+pub type ReleaseErrorHandler = Arc<dyn Fn(&str, &NodelessError) + Send + Sync>;
+
+/// Webhook Deduplicator
+#[derive(Clone)]
+pub struct WebhookDeduplicator {
+    store: Arc<dyn SeenStore>,
+    retention: Duration,
+    lease: Duration,
+    on_release_error: Option<ReleaseErrorHandler>,
+}
+
+impl WebhookDeduplicator {
+    /// Create deduplicator
+    /// # Arguments
+    /// * `store` - Storage of seen keys
+    /// * `retention` - How long a handled key blocks further deliveries
+    pub fn new(store: Arc<dyn SeenStore>, retention: Duration) -> Self {
+        Self {
+            store,
+            retention,
+            lease: DEFAULT_LEASE,
+            on_release_error: None,
+        }
+    }
+
+    /// Set how long a claimed key blocks redeliveries while its handler runs
+    ///
+    /// Use a lease longer than the slowest handler, a delivery whose lease
+    /// ran out may be handled twice.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Register handler for keys that could not be released after a failure
+    ///
+    /// [`dispatch`](Self::dispatch) returns the error of the webhook handler,
+    /// the claim of such a key blocks redeliveries until its lease runs out.
+    pub fn on_release_error<F>(mut self, error_handler: F) -> Self
+    where
+        F: Fn(&str, &NodelessError) + Send + Sync + 'static,
+    {
+        self.on_release_error = Some(Arc::new(error_handler));
+        self
+    }
+
+    /// Key identifying the event of a delivery
+    ///
+    /// Built from the webhook type, the invoice or request id and the event,
+    /// so every status change of an invoice is handled exactly once.
+    pub fn key(delivery: &WebhookDelivery) -> String {
+        format!(
+            "{}:{}:{}",
+            delivery.type_.as_str(),
+            delivery.payload.uuid,
+            delivery.event().as_str()
+        )
+    }
+
+    /// Record `key` as seen
+    ///
+    /// Returns `false` if the key was already seen within the retention window
+    /// or is claimed by a running dispatch. Use this to deduplicate on a
+    /// delivery id of your own.
+    pub fn check(&self, key: &str) -> Result<bool, NodelessError> {
+        let now = chrono::Utc::now().timestamp();
+        if self.store.claim(key, now, now + secs(self.lease))? != SeenState::Unseen {
+            return Ok(false);
+        }
+        self.store.complete(key, now + secs(self.retention))?;
+        Ok(true)
+    }
+
+    /// Dispatch delivery unless it was already seen
+    ///
+    /// The key is claimed for the lease while the handler runs, marked done
+    /// when it succeeds and released when it fails, so a redelivery of the
+    /// event is processed.
+    pub async fn dispatch(
+        &self,
+        router: &WebhookRouter,
+        delivery: WebhookDelivery,
+    ) -> Result<DedupOutcome, NodelessError> {
+        let key = Self::key(&delivery);
+        let now = chrono::Utc::now().timestamp();
+        match self.store.claim(&key, now, now + secs(self.lease))? {
+            SeenState::Unseen => {}
+            SeenState::InProgress => return Ok(DedupOutcome::InProgress),
+            SeenState::Done => return Ok(DedupOutcome::Duplicate),
+        }
+
+        let handled = match router.dispatch(delivery).await {
+            Ok(handled) => handled,
+            Err(err) => {
+                if let Err(release_err) = self.store.remove(&key) {
+                    if let Some(on_release_error) = &self.on_release_error {
+                        on_release_error(&key, &release_err);
+                    }
+                }
+                return Err(err);
+            }
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        self.store.complete(&key, now + secs(self.retention))?;
+        Ok(if handled {
+            DedupOutcome::Handled
+        } else {
+            DedupOutcome::Unhandled
+        })
+    }
+
+    /// Remove expired keys
+    pub fn purge(&self) -> Result<(), NodelessError> {
+        self.store.purge(chrono::Utc::now().timestamp())
+    }
+}
+
+fn secs(duration: Duration) -> i64 {
+    i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::webhook::{WebHookType, WebhookEvent};
+
+    fn store() -> Arc<MemorySeenStore> {
+        Arc::new(MemorySeenStore::new(NonZeroUsize::new(16).unwrap()))
+    }
+
+    fn delivery() -> WebhookDelivery {
+        WebhookDelivery::from_raw(
+            WebHookType::Store,
+            json!({"uuid": "invoice-id", "status": "paid"}),
+        )
+        .unwrap()
+    }
+
+    fn router() -> WebhookRouter {
+        WebhookRouter::new().on(WebHookType::Store, WebhookEvent::Paid, |_| async { Ok(()) })
+    }
+
+    #[test]
+    fn claim_blocks_until_lease_runs_out() {
+        let store = store();
+        assert_eq!(store.claim("key", 100, 160).unwrap(), SeenState::Unseen);
+        assert_eq!(store.claim("key", 159, 219).unwrap(), SeenState::InProgress);
+        assert_eq!(store.claim("key", 160, 220).unwrap(), SeenState::Unseen);
+    }
+
+    #[test]
+    fn completed_key_is_done_until_it_expires() {
+        let store = store();
+        store.claim("key", 100, 160).unwrap();
+        store.complete("key", 1_000).unwrap();
+        assert_eq!(store.claim("key", 500, 560).unwrap(), SeenState::Done);
+        assert_eq!(store.claim("key", 1_000, 1_060).unwrap(), SeenState::Unseen);
+    }
+
+    #[test]
+    fn purge_removes_expired_entries() {
+        let store = store();
+        store.claim("expired", 100, 160).unwrap();
+        store.claim("current", 100, 300).unwrap();
+        store.purge(200).unwrap();
+        assert_eq!(store.claim("expired", 200, 260).unwrap(), SeenState::Unseen);
+        assert_eq!(
+            store.claim("current", 200, 260).unwrap(),
+            SeenState::InProgress
+        );
+    }
+
+    #[test]
+    fn full_store_refuses_unexpired_eviction() {
+        let store = MemorySeenStore::new(NonZeroUsize::new(2).unwrap());
+        store.claim("first", 100, 160).unwrap();
+        store.claim("second", 100, 1_000).unwrap();
+        store.complete("second", 1_000).unwrap();
+
+        assert!(matches!(
+            store.claim("third", 120, 180),
+            Err(NodelessError::SeenStoreFull(2))
+        ));
+        assert_eq!(store.claim("second", 120, 180).unwrap(), SeenState::Done);
+
+        // An expired entry makes room for the new key
+        assert_eq!(store.claim("third", 160, 220).unwrap(), SeenState::Unseen);
+        assert_eq!(store.claim("second", 160, 220).unwrap(), SeenState::Done);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store_tracks_claims() {
+        let store =
+            SqliteSeenStore::from_connection(rusqlite::Connection::open_in_memory().unwrap())
+                .unwrap();
+        assert_eq!(store.claim("key", 100, 160).unwrap(), SeenState::Unseen);
+        assert_eq!(store.claim("key", 120, 180).unwrap(), SeenState::InProgress);
+        store.complete("key", 1_000).unwrap();
+        assert_eq!(store.claim("key", 500, 560).unwrap(), SeenState::Done);
+        store.purge(1_000).unwrap();
+        assert_eq!(store.claim("key", 1_000, 1_060).unwrap(), SeenState::Unseen);
+    }
+
+    #[tokio::test]
+    async fn handled_delivery_is_duplicate() {
+        let deduplicator = WebhookDeduplicator::new(store(), Duration::from_secs(3600));
+        let router = router();
+
+        let outcome = deduplicator.dispatch(&router, delivery()).await.unwrap();
+        assert_eq!(outcome, DedupOutcome::Handled);
+        let outcome = deduplicator.dispatch(&router, delivery()).await.unwrap();
+        assert_eq!(outcome, DedupOutcome::Duplicate);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_released() {
+        let deduplicator = WebhookDeduplicator::new(store(), Duration::from_secs(3600));
+        let failing = WebhookRouter::new().on(WebHookType::Store, WebhookEvent::Paid, |_| async {
+            Err("handler failed".into())
+        });
+
+        assert!(deduplicator.dispatch(&failing, delivery()).await.is_err());
+        let outcome = deduplicator.dispatch(&router(), delivery()).await.unwrap();
+        assert_eq!(outcome, DedupOutcome::Handled);
+    }
+
+    struct UnreleasableStore(MemorySeenStore);
+
+    impl SeenStore for UnreleasableStore {
+        fn claim(&self, key: &str, now: i64, lease_until: i64) -> Result<SeenState, NodelessError> {
+            self.0.claim(key, now, lease_until)
+        }
+
+        fn complete(&self, key: &str, expires_at: i64) -> Result<(), NodelessError> {
+            self.0.complete(key, expires_at)
+        }
+
+        fn remove(&self, _key: &str) -> Result<(), NodelessError> {
+            Err(NodelessError::InvalidResponse)
+        }
+
+        fn purge(&self, now: i64) -> Result<(), NodelessError> {
+            self.0.purge(now)
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_release_keeps_handler_error() {
+        let release_errors = Arc::new(Mutex::new(Vec::new()));
+        let store = UnreleasableStore(MemorySeenStore::new(NonZeroUsize::new(16).unwrap()));
+        let deduplicator = {
+            let release_errors = release_errors.clone();
+            WebhookDeduplicator::new(Arc::new(store), Duration::from_secs(3600)).on_release_error(
+                move |key, err| {
+                    release_errors
+                        .lock()
+                        .unwrap()
+                        .push((key.to_string(), err.to_string()));
+                },
+            )
+        };
+        let failing = WebhookRouter::new().on(WebHookType::Store, WebhookEvent::Paid, |_| async {
+            Err("handler failed".into())
+        });
+
+        let err = deduplicator
+            .dispatch(&failing, delivery())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, NodelessError::HandlerError(err) if err.to_string() == "handler failed")
+        );
+        assert_eq!(
+            *release_errors.lock().unwrap(),
+            vec![(
+                WebhookDeduplicator::key(&delivery()),
+                NodelessError::InvalidResponse.to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn claimed_delivery_is_in_progress() {
+        let store = store();
+        let deduplicator = WebhookDeduplicator::new(store.clone(), Duration::from_secs(3600));
+
+        let now = chrono::Utc::now().timestamp();
+        store
+            .claim(&WebhookDeduplicator::key(&delivery()), now, now + 60)
+            .unwrap();
+        let outcome = deduplicator.dispatch(&router(), delivery()).await.unwrap();
+        assert_eq!(outcome, DedupOutcome::InProgress);
+    }
+
+    #[tokio::test]
+    async fn crashed_delivery_is_handled_after_lease() {
+        let store = store();
+        let deduplicator = WebhookDeduplicator::new(store.clone(), Duration::from_secs(3600));
+
+        // Claimed by a dispatch that never completed or released the key
+        let now = chrono::Utc::now().timestamp();
+        store
+            .claim(&WebhookDeduplicator::key(&delivery()), now - 120, now - 60)
+            .unwrap();
+        let outcome = deduplicator.dispatch(&router(), delivery()).await.unwrap();
+        assert_eq!(outcome, DedupOutcome::Handled);
+    }
+}
@@ -0,0 +1,440 @@
+//! Webhook Enrichment
+//!
+//! Webhook payloads only carry the id and status of an invoice.
+//! [`WebhookEnricher`] fetches the full [`Invoice`] or
+//! [`PaywallRequest`](crate::paywall::PaywallRequest) for a delivery, caching
+//! the result and limiting concurrent requests.
+//! Concurrent deliveries for the same resource share a single request.
+//!
+//! # Example
+//! ```
+//! use nodeless_rs::webhook::{WebHookType, WebhookDelivery, WebhookEvent, WebhookTarget};
+//! use nodeless_rs::webhook_enrich::WebhookEnricher;
+//! use nodeless_rs::webhook_router::WebhookRouter;
+//! use nodeless_rs::Nodeless;
+//!
+//! let client = Nodeless::new("xxxxxxxxxxx", None).unwrap();
+//! let enricher = WebhookEnricher::new(client);
+//!
+//! // Invoices are created with the id of their store in the metadata
+//! let store_of = |delivery: &WebhookDelivery| {
+//!     let store_id = delivery
+//!         .payload
+//!         .metadata
+//!         .as_ref()
+//!         .and_then(|metadata| metadata["storeId"].as_str())
+//!         .unwrap_or("default-store-id");
+//!     WebhookTarget::Store(store_id.to_string())
+//! };
+//!
+//! let router = WebhookRouter::new().on(
+//!     WebHookType::Store,
+//!     WebhookEvent::Paid,
+//!     enricher.handler(store_of, |enriched| async move {
+//!         println!("{:?}", enriched.resource);
+//!         Ok(())
+//!     }),
+//! );
+//! ```
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use lru::LruCache;
+use tokio::sync::{OnceCell, Semaphore};
+
+use crate::error::NodelessError;
+use crate::paywall::RawPaywallRequest;
+use crate::store::Invoice;
+use crate::webhook::{WebhookDelivery, WebhookEvent, WebhookTarget};
+use crate::webhook_router::HandlerError;
+use crate::Nodeless;
+
+/// Resource a delivery was sent for
+#[derive(Clone, Debug)]
+pub enum EnrichedResource {
+    Invoice(Box<Invoice>),
+    /// Request with its metadata json, which may be a list or an object
+    PaywallRequest(Box<RawPaywallRequest>),
+}
+
+/// Delivery together with the resource it was sent for
+#[derive(Clone, Debug)]
+pub struct EnrichedDelivery {
+    pub delivery: WebhookDelivery,
+    pub resource: EnrichedResource,
+}
+
+/// Fetcher of the resource a delivery was sent for
+///
+/// Implemented by [`Nodeless`], which gets the invoice or paywall request
+/// from the api.
+pub trait ResourceFetcher: Send + Sync {
+    /// Fetch the invoice or request `id` of `target`
+    fn fetch<'a>(
+        &'a self,
+        target: &'a WebhookTarget,
+        id: &'a str,
+    ) -> BoxFuture<'a, Result<EnrichedResource, NodelessError>>;
+}
+
+impl ResourceFetcher for Nodeless {
+    fn fetch<'a>(
+        &'a self,
+        target: &'a WebhookTarget,
+        id: &'a str,
+    ) -> BoxFuture<'a, Result<EnrichedResource, NodelessError>> {
+        Box::pin(async move {
+            Ok(match target {
+                WebhookTarget::Store(store_id) => {
+                    EnrichedResource::Invoice(Box::new(self.get_store_invoice(store_id, id).await?))
+                }
+                WebhookTarget::Paywall(paywall_id) => EnrichedResource::PaywallRequest(Box::new(
+                    self.get_paywall_request_with_metadata(paywall_id, id)
+                        .await?,
+                )),
+            })
+        })
+    }
+}
+
+type CacheKey = (WebhookTarget, String, WebhookEvent);
+
+/// Webhook Enricher
+#[derive(Clone)]
+pub struct WebhookEnricher {
+    fetcher: Arc<dyn ResourceFetcher>,
+    permits: Arc<Semaphore>,
+    cache: Arc<Mutex<LruCache<CacheKey, (Instant, EnrichedResource)>>>,
+    ttl: Duration,
+    in_flight: Arc<Mutex<HashMap<CacheKey, Arc<OnceCell<EnrichedResource>>>>>,
+}
+
+impl WebhookEnricher {
+    /// Create enricher with at most 4 concurrent requests and a cache of
+    /// 1000 resources kept for 60 seconds
+    pub fn new(client: Nodeless) -> Self {
+        Self::with_fetcher(Arc::new(client))
+    }
+
+    /// Create enricher fetching resources with `fetcher`
+    pub fn with_fetcher(fetcher: Arc<dyn ResourceFetcher>) -> Self {
+        Self {
+            fetcher,
+            permits: Arc::new(Semaphore::new(4)),
+            cache: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(1000).expect("non zero"),
+            ))),
+            ttl: Duration::from_secs(60),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Set maximum number of concurrent requests
+    pub fn with_concurrency(mut self, max_concurrent: NonZeroUsize) -> Self {
+        self.permits = Arc::new(Semaphore::new(max_concurrent.get()));
+        self
+    }
+
+    /// Set number of cached resources and how long they are kept
+    pub fn with_cache(mut self, capacity: NonZeroUsize, ttl: Duration) -> Self {
+        self.cache = Arc::new(Mutex::new(LruCache::new(capacity)));
+        self.ttl = ttl;
+        self
+    }
+
+    /// Fetch the resource of `delivery`
+    /// # Arguments
+    /// * `target` - Store or paywall the webhook belongs to
+    /// * `delivery` - Verified delivery
+    pub async fn enrich(
+        &self,
+        target: &WebhookTarget,
+        delivery: WebhookDelivery,
+    ) -> Result<EnrichedDelivery, NodelessError> {
+        let key = (
+            target.clone(),
+            delivery.payload.uuid.clone(),
+            delivery.event().clone(),
+        );
+
+        if let Some(resource) = self.cached(&key) {
+            return Ok(EnrichedDelivery { delivery, resource });
+        }
+
+        // Deliveries for the same key wait for the first request instead of
+        // sending their own, a failed request is retried by the next waiter
+        let cell = self
+            .in_flight
+            .lock()
+            .expect("enricher in flight lock poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let resource = cell
+            .get_or_try_init(|| async {
+                let _permit = self
+                    .permits
+                    .acquire()
+                    .await
+                    .expect("enricher semaphore is never closed");
+                let resource = self.fetcher.fetch(target, &delivery.payload.uuid).await?;
+                // Cached before the request leaves the in flight map, so later
+                // deliveries find it in one of them
+                self.cache
+                    .lock()
+                    .expect("enricher cache lock poisoned")
+                    .put(key.clone(), (Instant::now(), resource.clone()));
+                Ok::<_, NodelessError>(resource)
+            })
+            .await
+            .cloned();
+
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("enricher in flight lock poisoned");
+        if in_flight
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell) && current.initialized())
+        {
+            in_flight.remove(&key);
+        }
+        drop(in_flight);
+
+        Ok(EnrichedDelivery {
+            delivery,
+            resource: resource?,
+        })
+    }
+
+    /// Wrap `handler` into a [`WebhookRouter`](crate::webhook_router::WebhookRouter)
+    /// handler receiving enriched deliveries
+    /// # Arguments
+    /// * `target` - Resolves the store or paywall of each delivery, routes
+    ///   only match on webhook type and event
+    /// * `handler` - Handler of the enriched deliveries
+    pub fn handler<T, F, Fut>(
+        &self,
+        target: T,
+        handler: F,
+    ) -> impl Fn(WebhookDelivery) -> BoxFuture<'static, Result<(), HandlerError>> + Send + Sync + 'static
+    where
+        T: Fn(&WebhookDelivery) -> WebhookTarget + Send + Sync + 'static,
+        F: Fn(EnrichedDelivery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        let enricher = self.clone();
+        let handler = Arc::new(handler);
+        move |delivery| -> BoxFuture<'static, Result<(), HandlerError>> {
+            let enricher = enricher.clone();
+            let handler = handler.clone();
+            let target = target(&delivery);
+            Box::pin(async move {
+                let enriched = enricher.enrich(&target, delivery).await?;
+                handler(enriched).await
+            })
+        }
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<EnrichedResource> {
+        let mut cache = self.cache.lock().expect("enricher cache lock poisoned");
+        match cache.get(key) {
+            Some((fetched_at, resource)) if fetched_at.elapsed() < self.ttl => {
+                Some(resource.clone())
+            }
+            Some(_) => {
+                cache.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::webhook::WebHookType;
+
+    /// Fetcher counting requests, each taking `delay`
+    #[derive(Default)]
+    struct FakeFetcher {
+        calls: AtomicUsize,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        delay: Duration,
+    }
+
+    impl ResourceFetcher for FakeFetcher {
+        fn fetch<'a>(
+            &'a self,
+            _target: &'a WebhookTarget,
+            id: &'a str,
+        ) -> BoxFuture<'a, Result<EnrichedResource, NodelessError>> {
+            Box::pin(async move {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_running.fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(self.delay).await;
+                self.running.fetch_sub(1, Ordering::SeqCst);
+                Ok(request(id))
+            })
+        }
+    }
+
+    fn fetcher(delay: Duration) -> Arc<FakeFetcher> {
+        Arc::new(FakeFetcher {
+            delay,
+            ..FakeFetcher::default()
+        })
+    }
+
+    fn calls(fetcher: &FakeFetcher) -> usize {
+        fetcher.calls.load(Ordering::SeqCst)
+    }
+
+    fn target() -> WebhookTarget {
+        WebhookTarget::Paywall("paywall-id".to_string())
+    }
+
+    fn delivery(uuid: &str) -> WebhookDelivery {
+        WebhookDelivery::from_raw(
+            WebHookType::Paywall,
+            json!({"uuid": uuid, "status": "paid"}),
+        )
+        .unwrap()
+    }
+
+    fn request(id: &str) -> EnrichedResource {
+        EnrichedResource::PaywallRequest(Box::new(
+            serde_json::from_value(json!({
+                "id": id,
+                "satsAmount": 2100,
+                "status": "paid",
+                "metadata": [],
+                "createdAt": "2023-06-01T12:00:00.000000Z",
+                "paidAt": null,
+                "onchainAddress": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+                "lightningInvoice": "lnbc21u1p...",
+                "paywall": null
+            }))
+            .unwrap(),
+        ))
+    }
+
+    fn request_id(enriched: &EnrichedDelivery) -> &str {
+        match &enriched.resource {
+            EnrichedResource::PaywallRequest(request) => &request.id,
+            EnrichedResource::Invoice(_) => panic!("expected a paywall request"),
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_resource_is_not_fetched_again() {
+        let fetcher = fetcher(Duration::ZERO);
+        let enricher = WebhookEnricher::with_fetcher(fetcher.clone());
+
+        let first = enricher
+            .enrich(&target(), delivery("request-1"))
+            .await
+            .unwrap();
+        let second = enricher
+            .enrich(&target(), delivery("request-1"))
+            .await
+            .unwrap();
+        assert_eq!(request_id(&first), "request-1");
+        assert_eq!(request_id(&second), "request-1");
+        assert_eq!(calls(&fetcher), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_resource_is_fetched_again() {
+        let fetcher = fetcher(Duration::ZERO);
+        let enricher = WebhookEnricher::with_fetcher(fetcher.clone())
+            .with_cache(NonZeroUsize::new(10).unwrap(), Duration::from_millis(20));
+
+        enricher
+            .enrich(&target(), delivery("request-1"))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        enricher
+            .enrich(&target(), delivery("request-1"))
+            .await
+            .unwrap();
+        assert_eq!(calls(&fetcher), 2);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_resource_is_evicted() {
+        let fetcher = fetcher(Duration::ZERO);
+        let enricher = WebhookEnricher::with_fetcher(fetcher.clone())
+            .with_cache(NonZeroUsize::new(1).unwrap(), Duration::from_secs(60));
+
+        for uuid in ["request-1", "request-2", "request-1"] {
+            enricher.enrich(&target(), delivery(uuid)).await.unwrap();
+        }
+        assert_eq!(calls(&fetcher), 3);
+    }
+
+    #[tokio::test]
+    async fn concurrent_deliveries_share_one_request() {
+        let fetcher = fetcher(Duration::from_millis(20));
+        let enricher = WebhookEnricher::with_fetcher(fetcher.clone());
+
+        let target = target();
+        let (first, second, third) = tokio::join!(
+            enricher.enrich(&target, delivery("request-1")),
+            enricher.enrich(&target, delivery("request-1")),
+            enricher.enrich(&target, delivery("request-1")),
+        );
+        assert_eq!(request_id(&first.unwrap()), "request-1");
+        assert_eq!(request_id(&second.unwrap()), "request-1");
+        assert_eq!(request_id(&third.unwrap()), "request-1");
+        assert_eq!(calls(&fetcher), 1);
+        assert!(enricher.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrency_is_limited() {
+        let fetcher = fetcher(Duration::from_millis(10));
+        let enricher = WebhookEnricher::with_fetcher(fetcher.clone())
+            .with_concurrency(NonZeroUsize::new(2).unwrap());
+
+        let target = target();
+        let (a, b, c, d) = tokio::join!(
+            enricher.enrich(&target, delivery("request-1")),
+            enricher.enrich(&target, delivery("request-2")),
+            enricher.enrich(&target, delivery("request-3")),
+            enricher.enrich(&target, delivery("request-4")),
+        );
+        assert!(a.is_ok() && b.is_ok() && c.is_ok() && d.is_ok());
+        assert_eq!(calls(&fetcher), 4);
+        assert_eq!(fetcher.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn handler_resolves_target_per_delivery() {
+        let resolved = Arc::new(Mutex::new(Vec::new()));
+        let record = resolved.clone();
+        let handler = WebhookEnricher::with_fetcher(fetcher(Duration::ZERO)).handler(
+            move |delivery: &WebhookDelivery| {
+                record.lock().unwrap().push(delivery.payload.uuid.clone());
+                WebhookTarget::Paywall(format!("paywall-{}", delivery.payload.uuid))
+            },
+            |_| async { Ok(()) },
+        );
+
+        assert!(handler(delivery("invoice-1")).await.is_ok());
+        assert!(handler(delivery("invoice-2")).await.is_ok());
+        assert_eq!(*resolved.lock().unwrap(), vec!["invoice-1", "invoice-2"]);
+    }
+}
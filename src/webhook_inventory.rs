@@ -0,0 +1,435 @@
+//! Webhook Inventory
+//!
+//! Collects every webhook of the account and reports webhooks that need
+//! attention, with bulk actions to reactivate or prune them.
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::NodelessError;
+use crate::webhook::{CreateWebhook, Webhook, WebhookStatus, WebhookTarget};
+use crate::Nodeless;
+
+/// Webhook and the store or paywall it belongs to
+#[derive(Clone, Debug)]
+pub struct WebhookEntry {
+    pub target: WebhookTarget,
+    pub webhook: Webhook,
+}
+
+/// Problem found with a webhook
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WebhookIssue {
+    /// Webhook is not active
+    Inactive,
+    /// Nothing was delivered since the given timestamp, or ever
+    Stale { last_delivery_at: Option<i64> },
+    /// Another webhook of the account uses the same url
+    DuplicateUrl,
+    /// Url does not use https
+    InsecureUrl,
+}
+
+impl fmt::Display for WebhookIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookIssue::Inactive => write!(f, "inactive"),
+            WebhookIssue::Stale {
+                last_delivery_at: Some(at),
+            } => write!(f, "stale since {at}"),
+            WebhookIssue::Stale {
+                last_delivery_at: None,
+            } => write!(f, "never delivered"),
+            WebhookIssue::DuplicateUrl => write!(f, "duplicate url"),
+            WebhookIssue::InsecureUrl => write!(f, "insecure url"),
+        }
+    }
+}
+
+/// Health of a single webhook
+#[derive(Clone, Debug)]
+pub struct WebhookHealth {
+    pub entry: WebhookEntry,
+    pub issues: Vec<WebhookIssue>,
+}
+
+impl WebhookHealth {
+    /// Check if `issue` was found
+    pub fn has_issue(&self, issue: &WebhookIssue) -> bool {
+        self.issues
+            .iter()
+            .any(|found| std::mem::discriminant(found) == std::mem::discriminant(issue))
+    }
+}
+
+/// Webhook Health Report
+#[derive(Clone, Debug, Default)]
+pub struct WebhookHealthReport {
+    pub webhooks: Vec<WebhookHealth>,
+}
+
+impl WebhookHealthReport {
+    /// Build report for `entries`
+    /// # Arguments
+    /// * `entries` - Webhooks to check
+    /// * `stale_after` - Age of the last delivery after which a webhook is stale
+    /// * `now` - Unix timestamp to measure staleness from
+    pub fn new(entries: Vec<WebhookEntry>, stale_after: Duration, now: i64) -> Self {
+        let mut url_count: HashMap<String, usize> = HashMap::new();
+        for entry in &entries {
+            if let Some(url) = &entry.webhook.url {
+                *url_count.entry(url.to_string()).or_default() += 1;
+            }
+        }
+
+        let stale_before =
+            now.saturating_sub(i64::try_from(stale_after.as_secs()).unwrap_or(i64::MAX));
+        let webhooks = entries
+            .into_iter()
+            .map(|entry| {
+                let webhook = &entry.webhook;
+                let mut issues = Vec::new();
+
+                if webhook.status.as_ref() != Some(&WebhookStatus::Active) {
+                    issues.push(WebhookIssue::Inactive);
+                }
+                let last_activity = webhook.last_delivery_at.or(webhook.created_at);
+                if last_activity.map_or(true, |at| at < stale_before) {
+                    issues.push(WebhookIssue::Stale {
+                        last_delivery_at: webhook.last_delivery_at,
+                    });
+                }
+                if let Some(url) = &webhook.url {
+                    if url_count.get(url.as_str()).copied().unwrap_or_default() > 1 {
+                        issues.push(WebhookIssue::DuplicateUrl);
+                    }
+                    if url.scheme() != "https" {
+                        issues.push(WebhookIssue::InsecureUrl);
+                    }
+                }
+
+                WebhookHealth { entry, issues }
+            })
+            .collect();
+
+        Self { webhooks }
+    }
+
+    /// Webhooks with at least one issue
+    pub fn unhealthy(&self) -> impl Iterator<Item = &WebhookHealth> {
+        self.webhooks
+            .iter()
+            .filter(|health| !health.issues.is_empty())
+    }
+
+    /// Webhooks with `issue`
+    pub fn with_issue<'a>(
+        &'a self,
+        issue: &'a WebhookIssue,
+    ) -> impl Iterator<Item = &'a WebhookEntry> + 'a {
+        self.webhooks
+            .iter()
+            .filter(move |health| health.has_issue(issue))
+            .map(|health| &health.entry)
+    }
+}
+
+impl fmt::Display for WebhookHealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for health in self.unhealthy() {
+            let webhook = &health.entry.webhook;
+            let issues = health
+                .issues
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                f,
+                "{} {} {}: {}",
+                health.entry.target,
+                webhook.id.as_deref().unwrap_or("unknown"),
+                webhook.url.as_ref().map_or("unknown", |url| url.as_str()),
+                issues
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Secret returned by the api, or the one known by the caller
+fn reactivation_secret<F>(entry: &WebhookEntry, known_secret: F) -> Result<String, NodelessError>
+where
+    F: Fn(&WebhookEntry) -> Option<String>,
+{
+    entry
+        .webhook
+        .secret
+        .clone()
+        .or_else(|| known_secret(entry))
+        .ok_or_else(|| {
+            NodelessError::InvalidWebhook(format!(
+                "no secret known for webhook {}",
+                entry.webhook.id.as_deref().unwrap_or("unknown")
+            ))
+        })
+}
+
+impl Nodeless {
+    /// Get every webhook of every store and paywall
+    pub async fn webhook_inventory(&self) -> Result<Vec<WebhookEntry>, NodelessError> {
+        let mut targets: Vec<WebhookTarget> = self
+            .get_stores()
+            .await?
+            .into_iter()
+            .map(|store| WebhookTarget::Store(store.id))
+            .collect();
+        targets.extend(
+            self.get_paywalls()
+                .await?
+                .into_iter()
+                .filter_map(|paywall| paywall.id.map(WebhookTarget::Paywall)),
+        );
+
+        let mut entries = Vec::new();
+        for target in targets {
+            for webhook in self.get_webhooks(&target).await? {
+                entries.push(WebhookEntry {
+                    target: target.clone(),
+                    webhook,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Build health report of every webhook of the account
+    /// # Arguments
+    /// * `stale_after` - Age of the last delivery after which a webhook is stale
+    pub async fn webhook_health_report(
+        &self,
+        stale_after: Duration,
+    ) -> Result<WebhookHealthReport, NodelessError> {
+        let entries = self.webhook_inventory().await?;
+        Ok(WebhookHealthReport::new(
+            entries,
+            stale_after,
+            chrono::Utc::now().timestamp(),
+        ))
+    }
+
+    /// Set `entries` active, keeping url, events and secret
+    ///
+    /// The api does not always return the secret of a webhook, `known_secret`
+    /// supplies it for those entries. Returns the updated webhooks.
+    /// # Arguments
+    /// * `entries` - Webhooks to reactivate
+    /// * `known_secret` - Secret of an entry the api returned without one
+    pub async fn reactivate_webhooks<'a, I, F>(
+        &self,
+        entries: I,
+        known_secret: F,
+    ) -> Result<Vec<Webhook>, NodelessError>
+    where
+        I: IntoIterator<Item = &'a WebhookEntry>,
+        F: Fn(&WebhookEntry) -> Option<String>,
+    {
+        let mut updated = Vec::new();
+        for entry in entries {
+            let webhook = &entry.webhook;
+            let id = webhook.id.as_ref().ok_or(NodelessError::InvalidResponse)?;
+            let update = CreateWebhook {
+                type_: entry.target.webhook_type(),
+                url: webhook.url.clone().ok_or(NodelessError::InvalidResponse)?,
+                events: webhook
+                    .events
+                    .clone()
+                    .ok_or(NodelessError::InvalidResponse)?,
+                secret: reactivation_secret(entry, &known_secret)?,
+                status: WebhookStatus::Active,
+            };
+            updated.push(self.update_webhook(&entry.target, id, update).await?);
+        }
+        Ok(updated)
+    }
+
+    /// Delete `entries`
+    pub async fn prune_webhooks<'a, I>(&self, entries: I) -> Result<(), NodelessError>
+    where
+        I: IntoIterator<Item = &'a WebhookEntry>,
+    {
+        for entry in entries {
+            let id = entry
+                .webhook
+                .id
+                .as_ref()
+                .ok_or(NodelessError::InvalidResponse)?;
+            self.delete_webhook(&entry.target, id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn entry(
+        id: &str,
+        url: &str,
+        status: WebhookStatus,
+        last_delivery_at: Option<i64>,
+    ) -> WebhookEntry {
+        WebhookEntry {
+            target: WebhookTarget::Store("store-id".to_string()),
+            webhook: Webhook {
+                id: Some(id.to_string()),
+                secret: None,
+                status: Some(status),
+                events: Some(vec![]),
+                url: Some(Url::parse(url).unwrap()),
+                created_at: Some(NOW - 30 * DAY),
+                last_delivery_at,
+            },
+        }
+    }
+
+    fn issues(report: &WebhookHealthReport, id: &str) -> Vec<WebhookIssue> {
+        report
+            .webhooks
+            .iter()
+            .find(|health| health.entry.webhook.id.as_deref() == Some(id))
+            .unwrap()
+            .issues
+            .clone()
+    }
+
+    #[test]
+    fn classifies_webhooks() {
+        let report = WebhookHealthReport::new(
+            vec![
+                entry(
+                    "healthy",
+                    "https://a.example.com",
+                    WebhookStatus::Active,
+                    Some(NOW - DAY),
+                ),
+                entry(
+                    "inactive",
+                    "https://b.example.com",
+                    WebhookStatus::Inactive,
+                    Some(NOW - DAY),
+                ),
+                entry(
+                    "stale",
+                    "https://c.example.com",
+                    WebhookStatus::Active,
+                    Some(NOW - 8 * DAY),
+                ),
+                entry(
+                    "never",
+                    "https://d.example.com",
+                    WebhookStatus::Active,
+                    None,
+                ),
+                entry(
+                    "insecure",
+                    "http://e.example.com",
+                    WebhookStatus::Active,
+                    Some(NOW),
+                ),
+                entry(
+                    "duplicate",
+                    "https://f.example.com",
+                    WebhookStatus::Active,
+                    Some(NOW),
+                ),
+                entry(
+                    "duplicate-2",
+                    "https://f.example.com/",
+                    WebhookStatus::Active,
+                    Some(NOW),
+                ),
+            ],
+            Duration::from_secs(7 * DAY as u64),
+            NOW,
+        );
+
+        assert!(issues(&report, "healthy").is_empty());
+        assert_eq!(issues(&report, "inactive"), vec![WebhookIssue::Inactive]);
+        assert_eq!(
+            issues(&report, "stale"),
+            vec![WebhookIssue::Stale {
+                last_delivery_at: Some(NOW - 8 * DAY)
+            }]
+        );
+        assert_eq!(
+            issues(&report, "never"),
+            vec![WebhookIssue::Stale {
+                last_delivery_at: None
+            }]
+        );
+        assert_eq!(issues(&report, "insecure"), vec![WebhookIssue::InsecureUrl]);
+        assert_eq!(
+            issues(&report, "duplicate"),
+            vec![WebhookIssue::DuplicateUrl]
+        );
+
+        let inactive: Vec<_> = report.with_issue(&WebhookIssue::Inactive).collect();
+        assert_eq!(inactive.len(), 1);
+        assert_eq!(report.unhealthy().count(), 6);
+    }
+
+    #[test]
+    fn unknown_status_is_inactive() {
+        let report = WebhookHealthReport::new(
+            vec![entry(
+                "paused",
+                "https://a.example.com",
+                WebhookStatus::Unknown("paused".to_string()),
+                Some(NOW),
+            )],
+            Duration::from_secs(DAY as u64),
+            NOW,
+        );
+        assert_eq!(issues(&report, "paused"), vec![WebhookIssue::Inactive]);
+    }
+
+    #[test]
+    fn huge_stale_threshold_does_not_wrap() {
+        let report = WebhookHealthReport::new(
+            vec![entry(
+                "old",
+                "https://a.example.com",
+                WebhookStatus::Active,
+                Some(0),
+            )],
+            Duration::MAX,
+            NOW,
+        );
+        assert!(report.unhealthy().next().is_none());
+    }
+
+    #[test]
+    fn reactivation_falls_back_to_known_secret() {
+        let mut with_secret = entry("1", "https://a.example.com", WebhookStatus::Inactive, None);
+        with_secret.webhook.secret = Some("api".to_string());
+        let without_secret = entry("2", "https://b.example.com", WebhookStatus::Inactive, None);
+        let known = |_: &WebhookEntry| Some("known".to_string());
+
+        assert_eq!(reactivation_secret(&with_secret, known).unwrap(), "api");
+        assert_eq!(
+            reactivation_secret(&without_secret, known).unwrap(),
+            "known"
+        );
+        assert!(matches!(
+            reactivation_secret(&without_secret, |_| None),
+            Err(NodelessError::InvalidWebhook(_))
+        ));
+    }
+}
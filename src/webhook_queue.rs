@@ -0,0 +1,792 @@
+//! Webhook Queue
+//!
+//! Durable, at-least-once processing of webhook deliveries. A receiver
+//! verifies and persists each delivery with [`WebhookQueue::receive`] and
+//! acknowledges it right away. [`WebhookQueue::process_due`] or
+//! [`WebhookQueue::run`] then hands the deliveries to the [`WebhookRouter`],
+//! retrying failures with exponential backoff until they are moved to the
+//! dead letter list.
+//!
+//! Several workers may process the same store, every due delivery is claimed
+//! by one of them for a lease while its handler runs.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::error::NodelessError;
+use crate::webhook::{WebHookType, WebhookDelivery, WebhookVerifier};
+use crate::webhook_dedup::{DedupOutcome, WebhookDeduplicator};
+use crate::webhook_router::WebhookRouter;
+
+/// Delivery persisted in a [`QueueStore`]
+#[derive(Clone, Debug)]
+pub struct QueuedDelivery {
+    pub id: u64,
+    pub type_: WebHookType,
+    pub body: Value,
+    pub attempts: u32,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub received_at: i64,
+}
+
+/// Storage of queued deliveries
+pub trait QueueStore: Send + Sync {
+    /// Persist a delivery due at `received_at`, returning its id
+    fn push(
+        &self,
+        type_: &WebHookType,
+        body: &Value,
+        received_at: i64,
+    ) -> Result<u64, NodelessError>;
+
+    /// Claim pending deliveries due at `now`, oldest first
+    ///
+    /// Claimed deliveries are atomically rescheduled to `lease_until`, so no
+    /// other worker receives them until they are completed, rescheduled or
+    /// the lease runs out.
+    fn due(
+        &self,
+        now: i64,
+        lease_until: i64,
+        limit: usize,
+    ) -> Result<Vec<QueuedDelivery>, NodelessError>;
+
+    /// Remove a processed delivery
+    fn complete(&self, id: u64) -> Result<(), NodelessError>;
+
+    /// Record a failed attempt and schedule the next one
+    fn retry(&self, id: u64, next_attempt_at: i64, error: &str) -> Result<(), NodelessError>;
+
+    /// Schedule the next attempt without recording a failed one
+    fn defer(&self, id: u64, next_attempt_at: i64) -> Result<(), NodelessError>;
+
+    /// Record a failed attempt and move the delivery to the dead letter list
+    fn dead_letter(&self, id: u64, error: &str) -> Result<(), NodelessError>;
+
+    /// Deliveries on the dead letter list
+    fn dead_letters(&self) -> Result<Vec<QueuedDelivery>, NodelessError>;
+
+    /// Move a dead letter back to the queue, due at `now` with no attempts
+    fn replay(&self, id: u64, now: i64) -> Result<(), NodelessError>;
+}
+
+#[derive(Debug, Default)]
+struct MemoryQueue {
+    next_id: u64,
+    pending: BTreeMap<u64, QueuedDelivery>,
+    dead: BTreeMap<u64, QueuedDelivery>,
+}
+
+/// In memory [`QueueStore`]
+///
+/// Deliveries do not survive a restart, use it for tests and development.
+#[derive(Debug, Default)]
+pub struct MemoryQueueStore {
+    queue: Mutex<MemoryQueue>,
+}
+
+impl MemoryQueueStore {
+    /// Create empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl QueueStore for MemoryQueueStore {
+    fn push(
+        &self,
+        type_: &WebHookType,
+        body: &Value,
+        received_at: i64,
+    ) -> Result<u64, NodelessError> {
+        let mut queue = self.queue.lock().expect("queue lock poisoned");
+        queue.next_id += 1;
+        let id = queue.next_id;
+        queue.pending.insert(
+            id,
+            QueuedDelivery {
+                id,
+                type_: type_.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt_at: received_at,
+                last_error: None,
+                received_at,
+            },
+        );
+        Ok(id)
+    }
+
+    fn due(
+        &self,
+        now: i64,
+        lease_until: i64,
+        limit: usize,
+    ) -> Result<Vec<QueuedDelivery>, NodelessError> {
+        let mut queue = self.queue.lock().expect("queue lock poisoned");
+        Ok(queue
+            .pending
+            .values_mut()
+            .filter(|delivery| delivery.next_attempt_at <= now)
+            .take(limit)
+            .map(|delivery| {
+                delivery.next_attempt_at = lease_until;
+                delivery.clone()
+            })
+            .collect())
+    }
+
+    fn complete(&self, id: u64) -> Result<(), NodelessError> {
+        self.queue
+            .lock()
+            .expect("queue lock poisoned")
+            .pending
+            .remove(&id);
+        Ok(())
+    }
+
+    fn retry(&self, id: u64, next_attempt_at: i64, error: &str) -> Result<(), NodelessError> {
+        let mut queue = self.queue.lock().expect("queue lock poisoned");
+        if let Some(delivery) = queue.pending.get_mut(&id) {
+            delivery.attempts += 1;
+            delivery.next_attempt_at = next_attempt_at;
+            delivery.last_error = Some(error.to_string());
+        }
+        Ok(())
+    }
+
+    fn defer(&self, id: u64, next_attempt_at: i64) -> Result<(), NodelessError> {
+        let mut queue = self.queue.lock().expect("queue lock poisoned");
+        if let Some(delivery) = queue.pending.get_mut(&id) {
+            delivery.next_attempt_at = next_attempt_at;
+        }
+        Ok(())
+    }
+
+    fn dead_letter(&self, id: u64, error: &str) -> Result<(), NodelessError> {
+        let mut queue = self.queue.lock().expect("queue lock poisoned");
+        if let Some(mut delivery) = queue.pending.remove(&id) {
+            delivery.attempts += 1;
+            delivery.last_error = Some(error.to_string());
+            queue.dead.insert(id, delivery);
+        }
+        Ok(())
+    }
+
+    fn dead_letters(&self) -> Result<Vec<QueuedDelivery>, NodelessError> {
+        let queue = self.queue.lock().expect("queue lock poisoned");
+        Ok(queue.dead.values().cloned().collect())
+    }
+
+    fn replay(&self, id: u64, now: i64) -> Result<(), NodelessError> {
+        let mut queue = self.queue.lock().expect("queue lock poisoned");
+        if let Some(mut delivery) = queue.dead.remove(&id) {
+            delivery.attempts = 0;
+            delivery.next_attempt_at = now;
+            queue.pending.insert(id, delivery);
+        }
+        Ok(())
+    }
+}
+
+/// SQLite backed [`QueueStore`]
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteQueueStore {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteQueueStore {
+    /// Open store at `path`, creating the table if needed
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, NodelessError> {
+        Self::from_connection(rusqlite::Connection::open(path)?)
+    }
+
+    /// Create store on an existing connection
+    pub fn from_connection(conn: rusqlite::Connection) -> Result<Self, NodelessError> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhook_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                type TEXT NOT NULL,
+                body TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at INTEGER NOT NULL,
+                last_error TEXT,
+                received_at INTEGER NOT NULL,
+                dead INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn query(
+        conn: &rusqlite::Connection,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<QueuedDelivery>, NodelessError> {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, u32>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        })?;
+
+        let mut deliveries = Vec::new();
+        for row in rows {
+            let (id, type_, body, attempts, next_attempt_at, last_error, received_at) = row?;
+            deliveries.push(QueuedDelivery {
+                id: id as u64,
+                type_: WebHookType::from(type_.as_str()),
+                body: serde_json::from_str(&body)?,
+                attempts,
+                next_attempt_at,
+                last_error,
+                received_at,
+            });
+        }
+        Ok(deliveries)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl QueueStore for SqliteQueueStore {
+    fn push(
+        &self,
+        type_: &WebHookType,
+        body: &Value,
+        received_at: i64,
+    ) -> Result<u64, NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        conn.execute(
+            "INSERT INTO webhook_queue (type, body, next_attempt_at, received_at)
+             VALUES (?1, ?2, ?3, ?3)",
+            rusqlite::params![type_.as_str(), body.to_string(), received_at],
+        )?;
+        Ok(conn.last_insert_rowid() as u64)
+    }
+
+    fn due(
+        &self,
+        now: i64,
+        lease_until: i64,
+        limit: usize,
+    ) -> Result<Vec<QueuedDelivery>, NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        // A single statement, so workers sharing the database never claim the
+        // same row
+        let mut deliveries = Self::query(
+            &conn,
+            "UPDATE webhook_queue SET next_attempt_at = ?2
+             WHERE id IN (
+                 SELECT id FROM webhook_queue WHERE dead = 0 AND next_attempt_at <= ?1
+                 ORDER BY id LIMIT ?3
+             )
+             RETURNING id, type, body, attempts, next_attempt_at, last_error, received_at",
+            rusqlite::params![now, lease_until, i64::try_from(limit).unwrap_or(i64::MAX)],
+        )?;
+        deliveries.sort_by_key(|delivery| delivery.id);
+        Ok(deliveries)
+    }
+
+    fn complete(&self, id: u64) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        conn.execute(
+            "DELETE FROM webhook_queue WHERE id = ?1",
+            rusqlite::params![id as i64],
+        )?;
+        Ok(())
+    }
+
+    fn retry(&self, id: u64, next_attempt_at: i64, error: &str) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        conn.execute(
+            "UPDATE webhook_queue
+             SET attempts = attempts + 1, next_attempt_at = ?2, last_error = ?3
+             WHERE id = ?1",
+            rusqlite::params![id as i64, next_attempt_at, error],
+        )?;
+        Ok(())
+    }
+
+    fn defer(&self, id: u64, next_attempt_at: i64) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        conn.execute(
+            "UPDATE webhook_queue SET next_attempt_at = ?2 WHERE id = ?1",
+            rusqlite::params![id as i64, next_attempt_at],
+        )?;
+        Ok(())
+    }
+
+    fn dead_letter(&self, id: u64, error: &str) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        conn.execute(
+            "UPDATE webhook_queue
+             SET attempts = attempts + 1, last_error = ?2, dead = 1
+             WHERE id = ?1",
+            rusqlite::params![id as i64, error],
+        )?;
+        Ok(())
+    }
+
+    fn dead_letters(&self) -> Result<Vec<QueuedDelivery>, NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        Self::query(
+            &conn,
+            "SELECT id, type, body, attempts, next_attempt_at, last_error, received_at
+             FROM webhook_queue WHERE dead = 1 ORDER BY id",
+            [],
+        )
+    }
+
+    fn replay(&self, id: u64, now: i64) -> Result<(), NodelessError> {
+        let conn = self.conn.lock().expect("queue lock poisoned");
+        conn.execute(
+            "UPDATE webhook_queue SET attempts = 0, next_attempt_at = ?2, dead = 0
+             WHERE id = ?1 AND dead = 1",
+            rusqlite::params![id as i64, now],
+        )?;
+        Ok(())
+    }
+}
+
+/// Retry Policy
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts before a delivery is dead lettered
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled on every further attempt
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt after `attempts` failed attempts
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Result of processing due deliveries
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessSummary {
+    /// Deliveries handled, skipped as duplicate or without a route
+    pub completed: usize,
+    /// Deliveries that failed and were scheduled for a retry
+    pub retried: usize,
+    /// Deliveries moved to the dead letter list
+    pub dead_lettered: usize,
+    /// Deliveries still being handled elsewhere, scheduled for a later round
+    pub deferred: usize,
+}
+
+/// Webhook Queue
+#[derive(Clone)]
+pub struct WebhookQueue {
+    store: Arc<dyn QueueStore>,
+    router: WebhookRouter,
+    deduplicator: Option<WebhookDeduplicator>,
+    retry_policy: RetryPolicy,
+    batch_size: usize,
+    lease: Duration,
+}
+
+impl WebhookQueue {
+    /// Create queue processing deliveries with `router`
+    pub fn new(store: Arc<dyn QueueStore>, router: WebhookRouter) -> Self {
+        Self {
+            store,
+            router,
+            deduplicator: None,
+            retry_policy: RetryPolicy::default(),
+            batch_size: 100,
+            lease: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Set retry policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Skip deliveries already seen by `deduplicator`
+    ///
+    /// Deliveries still claimed by another dispatch are deferred and not
+    /// counted as failed attempts, so they are processed once the claim is
+    /// released or its lease runs out.
+    pub fn with_deduplicator(mut self, deduplicator: WebhookDeduplicator) -> Self {
+        self.deduplicator = Some(deduplicator);
+        self
+    }
+
+    /// Set number of deliveries fetched per processing round
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Set how long a fetched batch is claimed by this worker
+    ///
+    /// Use a lease longer than handling a whole batch takes, deliveries whose
+    /// lease ran out are handed to other workers again. Defaults to 5 minutes.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Verify and persist a delivery
+    ///
+    /// Acknowledge the delivery to Nodeless once this returns `Ok`.
+    pub fn receive(
+        &self,
+        verifier: &WebhookVerifier,
+        body: &[u8],
+        signature: &str,
+    ) -> Result<u64, NodelessError> {
+        let delivery = verifier.verify(body, signature)?;
+        self.enqueue(&delivery)
+    }
+
+    /// Persist an already verified delivery
+    pub fn enqueue(&self, delivery: &WebhookDelivery) -> Result<u64, NodelessError> {
+        self.store.push(
+            &delivery.type_,
+            &delivery.raw,
+            chrono::Utc::now().timestamp(),
+        )
+    }
+
+    /// Process deliveries that are due
+    pub async fn process_due(&self) -> Result<ProcessSummary, NodelessError> {
+        let now = chrono::Utc::now().timestamp();
+        let mut summary = ProcessSummary::default();
+
+        let lease_until = now.saturating_add(secs(self.lease));
+        for queued in self.store.due(now, lease_until, self.batch_size)? {
+            let now = chrono::Utc::now().timestamp();
+            match self.handle(&queued).await {
+                Ok(DedupOutcome::InProgress) => {
+                    let backoff = secs(self.retry_policy.backoff(1));
+                    self.store.defer(queued.id, now.saturating_add(backoff))?;
+                    summary.deferred += 1;
+                }
+                Ok(_) => {
+                    self.store.complete(queued.id)?;
+                    summary.completed += 1;
+                }
+                Err(err) => {
+                    let attempts = queued.attempts + 1;
+                    if attempts >= self.retry_policy.max_attempts {
+                        self.store.dead_letter(queued.id, &err.to_string())?;
+                        summary.dead_lettered += 1;
+                    } else {
+                        let backoff = secs(self.retry_policy.backoff(attempts));
+                        self.store.retry(
+                            queued.id,
+                            now.saturating_add(backoff),
+                            &err.to_string(),
+                        )?;
+                        summary.retried += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Process due deliveries every `poll_interval`, forever
+    ///
+    /// Storage errors are returned, handler errors only reschedule.
+    pub async fn run(&self, poll_interval: Duration) -> Result<(), NodelessError> {
+        loop {
+            self.process_due().await?;
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Deliveries on the dead letter list
+    pub fn dead_letters(&self) -> Result<Vec<QueuedDelivery>, NodelessError> {
+        self.store.dead_letters()
+    }
+
+    /// Move a dead letter back to the queue for immediate processing
+    pub fn replay(&self, id: u64) -> Result<(), NodelessError> {
+        self.store.replay(id, chrono::Utc::now().timestamp())
+    }
+
+    async fn handle(&self, queued: &QueuedDelivery) -> Result<DedupOutcome, NodelessError> {
+        let delivery = WebhookDelivery::from_raw(queued.type_.clone(), queued.body.clone())?;
+        match &self.deduplicator {
+            Some(deduplicator) => deduplicator.dispatch(&self.router, delivery).await,
+            None => match self.router.dispatch(delivery).await? {
+                true => Ok(DedupOutcome::Handled),
+                false => Ok(DedupOutcome::Unhandled),
+            },
+        }
+    }
+}
+
+fn secs(duration: Duration) -> i64 {
+    i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::webhook::WebhookEvent;
+    use crate::webhook_dedup::{MemorySeenStore, SeenStore};
+
+    /// Router whose handler fails the first `failures` calls
+    fn router(failures: usize) -> (WebhookRouter, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let router = WebhookRouter::new().on(WebHookType::Store, WebhookEvent::Paid, move |_| {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if call < failures {
+                    Err("handler failed".into())
+                } else {
+                    Ok(())
+                }
+            }
+        });
+        (router, calls)
+    }
+
+    fn policy(max_attempts: u32, initial_backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff,
+            max_backoff: Duration::from_secs(60 * 60),
+        }
+    }
+
+    fn enqueue(queue: &WebhookQueue) -> u64 {
+        let delivery = WebhookDelivery::from_raw(
+            WebHookType::Store,
+            json!({"uuid": "invoice-id", "status": "paid"}),
+        )
+        .unwrap();
+        queue.enqueue(&delivery).unwrap()
+    }
+
+    fn pending(store: &MemoryQueueStore) -> Vec<QueuedDelivery> {
+        let queue = store.queue.lock().unwrap();
+        queue.pending.values().cloned().collect()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = policy(8, Duration::from_secs(5));
+        assert_eq!(policy.backoff(1), Duration::from_secs(5));
+        assert_eq!(policy.backoff(2), Duration::from_secs(10));
+        assert_eq!(policy.backoff(4), Duration::from_secs(40));
+        assert_eq!(policy.backoff(30), Duration::from_secs(60 * 60));
+    }
+
+    fn claims_due_deliveries_for_lease(store: &dyn QueueStore) {
+        let body = json!({"uuid": "invoice-id", "status": "paid"});
+        let first = store.push(&WebHookType::Store, &body, 100).unwrap();
+        let second = store.push(&WebHookType::Store, &body, 100).unwrap();
+        store.push(&WebHookType::Store, &body, 500).unwrap();
+
+        let claimed = store.due(200, 260, 10).unwrap();
+        let ids: Vec<u64> = claimed.iter().map(|delivery| delivery.id).collect();
+        assert_eq!(ids, vec![first, second]);
+        assert!(claimed
+            .iter()
+            .all(|delivery| delivery.next_attempt_at == 260));
+
+        // Claimed deliveries are not handed out again until the lease runs out
+        assert!(store.due(259, 319, 10).unwrap().is_empty());
+        store.complete(first).unwrap();
+        let reclaimed = store.due(260, 320, 10).unwrap();
+        assert_eq!(reclaimed.len(), 1);
+        assert_eq!(reclaimed[0].id, second);
+
+        // A retry releases the claim with its own schedule
+        store.retry(second, 300, "handler failed").unwrap();
+        let retried = store.due(300, 360, 10).unwrap();
+        assert_eq!(retried[0].id, second);
+        assert_eq!(retried[0].attempts, 1);
+        assert_eq!(retried[0].last_error.as_deref(), Some("handler failed"));
+
+        store.dead_letter(second, "handler failed").unwrap();
+        assert_eq!(store.due(1_000, 1_060, 10).unwrap().len(), 1);
+        assert_eq!(store.dead_letters().unwrap()[0].id, second);
+        store.replay(second, 2_000).unwrap();
+        assert!(store.dead_letters().unwrap().is_empty());
+        assert_eq!(store.due(2_000, 2_060, 1).unwrap()[0].id, second);
+    }
+
+    #[test]
+    fn memory_store_claims_due_deliveries() {
+        claims_due_deliveries_for_lease(&MemoryQueueStore::new());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store_claims_due_deliveries() {
+        let store =
+            SqliteQueueStore::from_connection(rusqlite::Connection::open_in_memory().unwrap())
+                .unwrap();
+        claims_due_deliveries_for_lease(&store);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_workers_do_not_share_deliveries() {
+        let path = std::env::temp_dir().join(format!(
+            "nodeless-queue-{}-{}.sqlite",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let first = SqliteQueueStore::open(&path).unwrap();
+        let second = SqliteQueueStore::open(&path).unwrap();
+
+        let body = json!({"uuid": "invoice-id", "status": "paid"});
+        for _ in 0..4 {
+            first.push(&WebHookType::Store, &body, 100).unwrap();
+        }
+
+        let a = first.due(200, 500, 3).unwrap();
+        let b = second.due(200, 500, 3).unwrap();
+        assert_eq!(a.len(), 3);
+        assert_eq!(b.len(), 1);
+        assert!(a.iter().all(|delivery| delivery.id != b[0].id));
+        assert!(first.due(200, 500, 10).unwrap().is_empty());
+
+        drop((first, second));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn handled_delivery_is_completed() {
+        let store = Arc::new(MemoryQueueStore::new());
+        let (router, calls) = router(0);
+        let queue = WebhookQueue::new(store.clone(), router);
+        enqueue(&queue);
+
+        let summary = queue.process_due().await.unwrap();
+        assert_eq!(summary.completed, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(pending(&store).is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_retried_with_backoff() {
+        let store = Arc::new(MemoryQueueStore::new());
+        let (router, _) = router(1);
+        let queue = WebhookQueue::new(store.clone(), router)
+            .with_retry_policy(policy(3, Duration::from_secs(60)));
+        enqueue(&queue);
+
+        let before = chrono::Utc::now().timestamp();
+        let summary = queue.process_due().await.unwrap();
+        assert_eq!(summary.retried, 1);
+
+        let retried = pending(&store);
+        assert_eq!(retried[0].attempts, 1);
+        assert!(retried[0].next_attempt_at >= before + 60);
+        assert!(retried[0].last_error.is_some());
+
+        // Not due before the backoff elapsed
+        let summary = queue.process_due().await.unwrap();
+        assert_eq!(summary, ProcessSummary::default());
+    }
+
+    #[tokio::test]
+    async fn delivery_is_dead_lettered_after_max_attempts() {
+        let store = Arc::new(MemoryQueueStore::new());
+        let (router, calls) = router(usize::MAX);
+        let queue =
+            WebhookQueue::new(store.clone(), router).with_retry_policy(policy(3, Duration::ZERO));
+        enqueue(&queue);
+
+        assert_eq!(queue.process_due().await.unwrap().retried, 1);
+        assert_eq!(queue.process_due().await.unwrap().retried, 1);
+        assert_eq!(queue.process_due().await.unwrap().dead_lettered, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        assert!(pending(&store).is_empty());
+        let dead = queue.dead_letters().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn replayed_dead_letter_is_processed() {
+        let store = Arc::new(MemoryQueueStore::new());
+        let (router, calls) = router(1);
+        let queue =
+            WebhookQueue::new(store.clone(), router).with_retry_policy(policy(1, Duration::ZERO));
+        let id = enqueue(&queue);
+
+        assert_eq!(queue.process_due().await.unwrap().dead_lettered, 1);
+        queue.replay(id).unwrap();
+        assert!(queue.dead_letters().unwrap().is_empty());
+        assert_eq!(pending(&store)[0].attempts, 0);
+
+        assert_eq!(queue.process_due().await.unwrap().completed, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(pending(&store).is_empty());
+    }
+
+    #[tokio::test]
+    async fn claimed_delivery_is_deferred() {
+        let store = Arc::new(MemoryQueueStore::new());
+        let seen = Arc::new(MemorySeenStore::new(NonZeroUsize::new(16).unwrap()));
+        let (router, calls) = router(0);
+        let queue = WebhookQueue::new(store.clone(), router)
+            .with_retry_policy(policy(3, Duration::from_secs(60)))
+            .with_deduplicator(WebhookDeduplicator::new(
+                seen.clone(),
+                Duration::from_secs(3600),
+            ));
+        enqueue(&queue);
+
+        // Claimed by a dispatch that crashed before completing
+        let now = chrono::Utc::now().timestamp();
+        seen.claim("store:invoice-id:paid", now, now + 300).unwrap();
+
+        let summary = queue.process_due().await.unwrap();
+        assert_eq!(summary.deferred, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let deferred = pending(&store);
+        assert_eq!(deferred[0].attempts, 0);
+        assert!(deferred[0].next_attempt_at >= now + 60);
+    }
+}
@@ -0,0 +1,409 @@
+//! Webhook Reconciliation
+//!
+//! Declare the webhooks every store and paywall should have and let
+//! [`Nodeless::reconcile_webhooks`] create, update and delete webhooks on the
+//! account until it matches. Webhooks are matched by url.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use url::Url;
+
+use crate::error::NodelessError;
+use crate::webhook::{CreateWebhook, Webhook, WebhookEvent, WebhookStatus, WebhookTarget};
+use crate::Nodeless;
+
+/// Desired Webhook
+#[derive(Clone, Debug)]
+pub struct DesiredWebhook {
+    pub url: Url,
+    pub events: Vec<WebhookEvent>,
+    pub secret: String,
+    pub status: WebhookStatus,
+}
+
+impl DesiredWebhook {
+    fn to_create(&self, target: &WebhookTarget) -> CreateWebhook {
+        CreateWebhook {
+            type_: target.webhook_type(),
+            url: self.url.clone(),
+            events: self.events.clone(),
+            secret: self.secret.clone(),
+            status: self.status.clone(),
+        }
+    }
+
+    /// Check if `webhook` differs from the desired state
+    ///
+    /// The secret is only compared when the api returns it.
+    fn differs(&self, webhook: &Webhook) -> bool {
+        let mut desired_events = self.events.clone();
+        desired_events.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        desired_events.dedup();
+        let mut current_events = webhook.events.clone().unwrap_or_default();
+        current_events.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        current_events.dedup();
+
+        desired_events != current_events
+            || webhook.status.as_ref() != Some(&self.status)
+            || webhook
+                .secret
+                .as_ref()
+                .is_some_and(|secret| secret != &self.secret)
+    }
+}
+
+/// Change needed to reach the desired state
+#[derive(Clone, Debug)]
+pub enum WebhookChange {
+    Create {
+        target: WebhookTarget,
+        webhook: CreateWebhook,
+    },
+    Update {
+        target: WebhookTarget,
+        current: Webhook,
+        webhook: CreateWebhook,
+    },
+    Delete {
+        target: WebhookTarget,
+        current: Webhook,
+    },
+}
+
+impl fmt::Display for WebhookChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookChange::Create { target, webhook } => write!(
+                f,
+                "+ {target} {} [{}] {}",
+                webhook.url,
+                events_list(&webhook.events),
+                webhook.status.as_str()
+            ),
+            WebhookChange::Update {
+                target,
+                current,
+                webhook,
+            } => write!(
+                f,
+                "~ {target} {} [{}] {} -> [{}] {}",
+                webhook.url,
+                events_list(current.events.as_deref().unwrap_or_default()),
+                current.status.as_ref().map_or("unknown", |s| s.as_str()),
+                events_list(&webhook.events),
+                webhook.status.as_str()
+            ),
+            WebhookChange::Delete { target, current } => write!(
+                f,
+                "- {target} {} [{}] {}",
+                current.url.as_ref().map_or("unknown", |url| url.as_str()),
+                events_list(current.events.as_deref().unwrap_or_default()),
+                current.status.as_ref().map_or("unknown", |s| s.as_str())
+            ),
+        }
+    }
+}
+
+/// Changes needed to reach the desired state
+#[derive(Clone, Debug, Default)]
+pub struct WebhookPlan {
+    pub changes: Vec<WebhookChange>,
+}
+
+impl WebhookPlan {
+    /// Check if the account already matches the desired state
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for WebhookPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "webhooks up to date");
+        }
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of applying a plan
+///
+/// Every change is attempted, a failed change does not stop the ones after it.
+#[derive(Debug, Default)]
+pub struct WebhookApplyReport {
+    /// Changes applied to the account
+    pub applied: Vec<WebhookChange>,
+    /// Changes that failed and their error
+    pub failed: Vec<(WebhookChange, NodelessError)>,
+}
+
+impl WebhookApplyReport {
+    /// Check if every change was applied
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for WebhookApplyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.applied {
+            writeln!(f, "{change}")?;
+        }
+        for (change, err) in &self.failed {
+            writeln!(f, "{change} failed: {err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Nodeless {
+    /// Plan changes to reach the desired webhooks of `target`
+    ///
+    /// Webhooks of `target` whose url is not desired are deleted, as are
+    /// further webhooks sharing the url of a desired one.
+    pub async fn plan_webhooks(
+        &self,
+        target: &WebhookTarget,
+        desired: &[DesiredWebhook],
+    ) -> Result<WebhookPlan, NodelessError> {
+        let current = self.get_webhooks(target).await?;
+        Ok(plan_changes(target, desired, current))
+    }
+
+    /// Apply planned changes
+    ///
+    /// Returns the applied and the failed changes, check
+    /// [`WebhookApplyReport::is_success`] before relying on the new state.
+    pub async fn apply_webhook_plan(&self, plan: &WebhookPlan) -> WebhookApplyReport {
+        let mut report = WebhookApplyReport::default();
+        for change in &plan.changes {
+            match self.apply_webhook_change(change).await {
+                Ok(()) => report.applied.push(change.clone()),
+                Err(err) => report.failed.push((change.clone(), err)),
+            }
+        }
+        report
+    }
+
+    async fn apply_webhook_change(&self, change: &WebhookChange) -> Result<(), NodelessError> {
+        match change {
+            WebhookChange::Create { target, webhook } => {
+                self.create_webhook(target, webhook.clone()).await?;
+            }
+            WebhookChange::Update {
+                target,
+                current,
+                webhook,
+            } => {
+                let id = current.id.as_ref().ok_or(NodelessError::InvalidResponse)?;
+                self.update_webhook(target, id, webhook.clone()).await?;
+            }
+            WebhookChange::Delete { target, current } => {
+                let id = current.id.as_ref().ok_or(NodelessError::InvalidResponse)?;
+                self.delete_webhook(target, id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconcile the webhooks of every listed target
+    ///
+    /// Returns the plan and, unless `dry_run` is set, the report of applying it.
+    /// # Arguments
+    /// * `desired` - Desired webhooks keyed by store or paywall
+    /// * `dry_run` - Only plan the changes, print the returned plan to review them
+    pub async fn reconcile_webhooks(
+        &self,
+        desired: &BTreeMap<WebhookTarget, Vec<DesiredWebhook>>,
+        dry_run: bool,
+    ) -> Result<(WebhookPlan, Option<WebhookApplyReport>), NodelessError> {
+        let mut plan = WebhookPlan::default();
+        for (target, webhooks) in desired {
+            plan.changes
+                .extend(self.plan_webhooks(target, webhooks).await?.changes);
+        }
+
+        if dry_run {
+            return Ok((plan, None));
+        }
+        let report = self.apply_webhook_plan(&plan).await;
+        Ok((plan, Some(report)))
+    }
+}
+
+fn plan_changes(
+    target: &WebhookTarget,
+    desired: &[DesiredWebhook],
+    current: Vec<Webhook>,
+) -> WebhookPlan {
+    let mut changes = Vec::new();
+
+    for want in desired {
+        match current.iter().find(|w| w.url.as_ref() == Some(&want.url)) {
+            Some(webhook) if want.differs(webhook) => changes.push(WebhookChange::Update {
+                target: target.clone(),
+                current: webhook.clone(),
+                webhook: want.to_create(target),
+            }),
+            Some(_) => (),
+            None => changes.push(WebhookChange::Create {
+                target: target.clone(),
+                webhook: want.to_create(target),
+            }),
+        }
+    }
+
+    // The first webhook with a desired url is kept, every further one deleted
+    let mut kept = HashSet::new();
+    for webhook in current {
+        let keep = webhook.url.as_ref().is_some_and(|url| {
+            desired.iter().any(|want| &want.url == url) && kept.insert(url.clone())
+        });
+        if !keep {
+            changes.push(WebhookChange::Delete {
+                target: target.clone(),
+                current: webhook,
+            });
+        }
+    }
+
+    WebhookPlan { changes }
+}
+
+fn events_list(events: &[WebhookEvent]) -> String {
+    events
+        .iter()
+        .map(|event| event.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desired(url: &str) -> DesiredWebhook {
+        DesiredWebhook {
+            url: Url::parse(url).unwrap(),
+            events: vec![WebhookEvent::Paid],
+            secret: "secret".to_string(),
+            status: WebhookStatus::Active,
+        }
+    }
+
+    fn webhook(id: &str, url: &str, events: Vec<WebhookEvent>) -> Webhook {
+        Webhook {
+            id: Some(id.to_string()),
+            secret: None,
+            status: Some(WebhookStatus::Active),
+            events: Some(events),
+            url: Some(Url::parse(url).unwrap()),
+            created_at: None,
+            last_delivery_at: None,
+        }
+    }
+
+    fn target() -> WebhookTarget {
+        WebhookTarget::Store("store-id".to_string())
+    }
+
+    fn ids(plan: &WebhookPlan) -> Vec<String> {
+        plan.changes
+            .iter()
+            .map(|change| match change {
+                WebhookChange::Create { webhook, .. } => format!("create {}", webhook.url),
+                WebhookChange::Update { current, .. } => {
+                    format!("update {}", current.id.as_deref().unwrap())
+                }
+                WebhookChange::Delete { current, .. } => {
+                    format!("delete {}", current.id.as_deref().unwrap())
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matching_webhooks_are_kept() {
+        let plan = plan_changes(
+            &target(),
+            &[desired("https://example.com/hook")],
+            vec![webhook(
+                "1",
+                "https://example.com/hook",
+                vec![WebhookEvent::Paid],
+            )],
+        );
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn plans_create_update_and_delete() {
+        let plan = plan_changes(
+            &target(),
+            &[
+                desired("https://example.com/new"),
+                desired("https://example.com/changed"),
+            ],
+            vec![
+                webhook("1", "https://example.com/changed", vec![WebhookEvent::New]),
+                webhook("2", "https://example.com/old", vec![WebhookEvent::Paid]),
+            ],
+        );
+        assert_eq!(
+            ids(&plan),
+            vec!["create https://example.com/new", "update 1", "delete 2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_continues_after_failed_change() {
+        let client = Nodeless::new("api-key", None).unwrap();
+        let mut without_id = webhook("1", "https://example.com/a", vec![WebhookEvent::Paid]);
+        without_id.id = None;
+        let plan = WebhookPlan {
+            changes: vec![
+                WebhookChange::Delete {
+                    target: target(),
+                    current: without_id.clone(),
+                },
+                WebhookChange::Update {
+                    target: target(),
+                    current: without_id,
+                    webhook: desired("https://example.com/a").to_create(&target()),
+                },
+            ],
+        };
+
+        // Both changes fail before reaching the api
+        let report = client.apply_webhook_plan(&plan).await;
+        assert!(!report.is_success());
+        assert!(report.applied.is_empty());
+        assert_eq!(report.failed.len(), 2);
+        assert!(report
+            .failed
+            .iter()
+            .all(|(_, err)| matches!(err, NodelessError::InvalidResponse)));
+        assert_eq!(
+            report.to_string(),
+            "- store store-id https://example.com/a [paid] active failed: Invalid Response\n\
+             ~ store store-id https://example.com/a [paid] active -> [paid] active failed: Invalid Response\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_urls_are_deleted() {
+        let plan = plan_changes(
+            &target(),
+            &[desired("https://example.com/hook")],
+            vec![
+                webhook("1", "https://example.com/hook", vec![WebhookEvent::Paid]),
+                webhook("2", "https://example.com/hook", vec![WebhookEvent::Paid]),
+                webhook("3", "https://example.com/hook", vec![WebhookEvent::New]),
+            ],
+        );
+        assert_eq!(ids(&plan), vec!["delete 2", "delete 3"]);
+    }
+}
@@ -0,0 +1,285 @@
+//! Webhook Router
+//!
+//! Dispatches verified webhook deliveries to async handlers registered per
+//! [`WebHookType`] and [`WebhookEvent`].
+//!
+//! # Example
+//! ```
+//! use nodeless_rs::webhook::{WebHookType, WebhookEvent};
+//! use nodeless_rs::webhook_router::WebhookRouter;
+//!
+//! let router = WebhookRouter::new()
+//!     .on(WebHookType::Store, WebhookEvent::Paid, |delivery| async move {
+//!         println!("invoice {} paid", delivery.payload.uuid);
+//!         Ok(())
+//!     })
+//!     .fallback(|_delivery| async move { Ok(()) });
+//! ```
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use crate::error::NodelessError;
+use crate::webhook::{WebHookType, WebhookDelivery, WebhookEvent};
+
+/// Error returned by a webhook handler
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+type Handler =
+    Arc<dyn Fn(WebhookDelivery) -> BoxFuture<'static, Result<(), HandlerError>> + Send + Sync>;
+type ErrorHandler = Arc<dyn Fn(&WebhookDelivery, &HandlerError) + Send + Sync>;
+
+type RouteKey = (WebHookType, WebhookEvent);
+
+/// Webhook Router
+#[derive(Clone, Default)]
+pub struct WebhookRouter {
+    routes: HashMap<RouteKey, Handler>,
+    error_handlers: HashMap<RouteKey, ErrorHandler>,
+    fallback: Option<Handler>,
+    fallback_error: Option<ErrorHandler>,
+    on_any_error: Option<ErrorHandler>,
+}
+
+impl WebhookRouter {
+    /// Create empty router
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register handler for `event` on webhooks of `type_`
+    ///
+    /// Registering a second handler for the same pair replaces the first,
+    /// an error handler registered for the pair is kept.
+    pub fn on<F, Fut>(mut self, type_: WebHookType, event: WebhookEvent, handler: F) -> Self
+    where
+        F: Fn(WebhookDelivery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        self.routes.insert((type_, event), box_handler(handler));
+        self
+    }
+
+    /// Register handler for deliveries without a matching route
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(WebhookDelivery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        self.fallback = Some(box_handler(handler));
+        self
+    }
+
+    /// Register error handler for the route of `event` on webhooks of `type_`
+    ///
+    /// Can be registered before or after the handler of the pair.
+    pub fn on_error<F>(mut self, type_: WebHookType, event: WebhookEvent, error_handler: F) -> Self
+    where
+        F: Fn(&WebhookDelivery, &HandlerError) + Send + Sync + 'static,
+    {
+        self.error_handlers
+            .insert((type_, event), Arc::new(error_handler));
+        self
+    }
+
+    /// Register error handler for the fallback handler
+    pub fn on_fallback_error<F>(mut self, error_handler: F) -> Self
+    where
+        F: Fn(&WebhookDelivery, &HandlerError) + Send + Sync + 'static,
+    {
+        self.fallback_error = Some(Arc::new(error_handler));
+        self
+    }
+
+    /// Register error handler called for failures of any handler
+    pub fn on_any_error<F>(mut self, error_handler: F) -> Self
+    where
+        F: Fn(&WebhookDelivery, &HandlerError) + Send + Sync + 'static,
+    {
+        self.on_any_error = Some(Arc::new(error_handler));
+        self
+    }
+
+    /// Check if a route is registered for `event` on webhooks of `type_`
+    pub fn has_route(&self, type_: &WebHookType, event: &WebhookEvent) -> bool {
+        self.routes.contains_key(&(type_.clone(), event.clone()))
+    }
+
+    /// Dispatch delivery to its handler
+    ///
+    /// Returns `false` when neither a route nor a fallback matched. A failing
+    /// handler is reported to its error handlers and returned as
+    /// [`NodelessError::HandlerError`] so the receiver can reject the
+    /// delivery and let Nodeless retry it.
+    pub async fn dispatch(&self, delivery: WebhookDelivery) -> Result<bool, NodelessError> {
+        let key = (delivery.type_.clone(), delivery.event().clone());
+        let (handler, on_error) = match self.routes.get(&key) {
+            Some(handler) => (handler, self.error_handlers.get(&key)),
+            None => match &self.fallback {
+                Some(handler) => (handler, self.fallback_error.as_ref()),
+                None => return Ok(false),
+            },
+        };
+
+        match handler(delivery.clone()).await {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                if let Some(on_error) = on_error {
+                    on_error(&delivery, &err);
+                }
+                if let Some(on_any_error) = &self.on_any_error {
+                    on_any_error(&delivery, &err);
+                }
+                Err(NodelessError::HandlerError(err))
+            }
+        }
+    }
+}
+
+fn box_handler<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(WebhookDelivery) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+{
+    Arc::new(
+        move |delivery| -> BoxFuture<'static, Result<(), HandlerError>> {
+            Box::pin(handler(delivery))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn delivery(type_: WebHookType, event: &str) -> WebhookDelivery {
+        WebhookDelivery::from_raw(type_, json!({ "uuid": "invoice-id", "status": event })).unwrap()
+    }
+
+    fn counter() -> Arc<AtomicUsize> {
+        Arc::new(AtomicUsize::new(0))
+    }
+
+    #[tokio::test]
+    async fn routes_to_matching_handler() {
+        let paid = counter();
+        let fallback = counter();
+        let router = {
+            let paid = paid.clone();
+            let fallback = fallback.clone();
+            WebhookRouter::new()
+                .on(WebHookType::Store, WebhookEvent::Paid, move |_| {
+                    let paid = paid.clone();
+                    async move {
+                        paid.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    }
+                })
+                .fallback(move |_| {
+                    let fallback = fallback.clone();
+                    async move {
+                        fallback.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    }
+                })
+        };
+
+        assert!(router
+            .dispatch(delivery(WebHookType::Store, "paid"))
+            .await
+            .unwrap());
+        assert_eq!(paid.load(Ordering::SeqCst), 1);
+        assert_eq!(fallback.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn unmatched_goes_to_fallback() {
+        let fallback = counter();
+        let router = {
+            let fallback = fallback.clone();
+            WebhookRouter::new()
+                .on(WebHookType::Store, WebhookEvent::Paid, |_| async { Ok(()) })
+                .fallback(move |_| {
+                    let fallback = fallback.clone();
+                    async move {
+                        fallback.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    }
+                })
+        };
+
+        assert!(router
+            .dispatch(delivery(WebHookType::Paywall, "paid"))
+            .await
+            .unwrap());
+        assert!(router
+            .dispatch(delivery(WebHookType::Store, "expired"))
+            .await
+            .unwrap());
+        assert_eq!(fallback.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unmatched_without_fallback_is_not_handled() {
+        let router =
+            WebhookRouter::new().on(WebHookType::Store, WebhookEvent::Paid, |_| async { Ok(()) });
+
+        assert!(!router
+            .dispatch(delivery(WebHookType::Store, "new"))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn error_handlers_are_called() {
+        let route_errors = counter();
+        let any_errors = counter();
+        let router = {
+            let route_errors = route_errors.clone();
+            let any_errors = any_errors.clone();
+            // Error handler registered before the handler and kept when the
+            // handler is replaced
+            WebhookRouter::new()
+                .on_error(WebHookType::Store, WebhookEvent::Paid, move |_, _| {
+                    route_errors.fetch_add(1, Ordering::SeqCst);
+                })
+                .on(WebHookType::Store, WebhookEvent::Paid, |_| async { Ok(()) })
+                .on(WebHookType::Store, WebhookEvent::Paid, |_| async {
+                    Err("handler failed".into())
+                })
+                .on_any_error(move |_, _| {
+                    any_errors.fetch_add(1, Ordering::SeqCst);
+                })
+        };
+
+        let result = router.dispatch(delivery(WebHookType::Store, "paid")).await;
+        assert!(matches!(result, Err(NodelessError::HandlerError(_))));
+        assert_eq!(route_errors.load(Ordering::SeqCst), 1);
+        assert_eq!(any_errors.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn fallback_error_handler_is_called() {
+        let fallback_errors = counter();
+        let router = {
+            let fallback_errors = fallback_errors.clone();
+            WebhookRouter::new()
+                .on_fallback_error(move |_, _| {
+                    fallback_errors.fetch_add(1, Ordering::SeqCst);
+                })
+                .fallback(|_| async { Err("fallback failed".into()) })
+        };
+
+        assert!(router
+            .dispatch(delivery(WebHookType::Inbox, "paid"))
+            .await
+            .is_err());
+        assert_eq!(fallback_errors.load(Ordering::SeqCst), 1);
+    }
+}
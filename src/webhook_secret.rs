@@ -0,0 +1,92 @@
+//! Webhook Secrets
+//!
+//! Generate webhook secrets and rotate them without failing deliveries.
+//!
+//! A rotation without downtime:
+//! 1. Generate a secret with [`generate_secret`]
+//! 2. Call [`WebhookVerifier::rotate`](crate::webhook::WebhookVerifier::rotate)
+//!    so the receiver accepts the old and the new secret
+//! 3. Update the webhook with [`Nodeless::rotate_webhook_secret`]
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::error::NodelessError;
+use crate::webhook::{CreateWebhook, Webhook, WebhookTarget};
+use crate::Nodeless;
+
+/// Length of generated secrets
+pub const SECRET_LENGTH: usize = 32;
+
+/// Generate a random alphanumeric webhook secret
+pub fn generate_secret() -> String {
+    rand::rngs::OsRng
+        .sample_iter(&Alphanumeric)
+        .take(SECRET_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+impl Nodeless {
+    /// Rotate Webhook Secret
+    ///
+    /// Updates the webhook to `new_secret`, keeping its url, events and status.
+    pub async fn rotate_webhook_secret(
+        &self,
+        target: &WebhookTarget,
+        webhook_id: &str,
+        new_secret: &str,
+    ) -> Result<Webhook, NodelessError> {
+        let current = self.get_webhook(target, webhook_id).await?;
+
+        let webhook = CreateWebhook {
+            type_: target.webhook_type(),
+            url: current.url.ok_or(NodelessError::InvalidResponse)?,
+            events: current.events.ok_or(NodelessError::InvalidResponse)?,
+            secret: new_secret.to_string(),
+            status: current.status.ok_or(NodelessError::InvalidResponse)?,
+        };
+
+        self.update_webhook(target, webhook_id, webhook).await
+    }
+
+    /// Rotate Store Webhook Secret
+    pub async fn rotate_store_webhook_secret(
+        &self,
+        store_id: &str,
+        webhook_id: &str,
+        new_secret: &str,
+    ) -> Result<Webhook, NodelessError> {
+        let target = WebhookTarget::Store(store_id.to_string());
+        self.rotate_webhook_secret(&target, webhook_id, new_secret)
+            .await
+    }
+
+    /// Rotate Paywall Webhook Secret
+    pub async fn rotate_paywall_webhook_secret(
+        &self,
+        paywall_id: &str,
+        webhook_id: &str,
+        new_secret: &str,
+    ) -> Result<Webhook, NodelessError> {
+        let target = WebhookTarget::Paywall(paywall_id.to_string());
+        self.rotate_webhook_secret(&target, webhook_id, new_secret)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_secret_is_alphanumeric() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), SECRET_LENGTH);
+        assert!(secret.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn generated_secrets_differ() {
+        assert_ne!(generate_secret(), generate_secret());
+    }
+}
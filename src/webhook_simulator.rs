@@ -0,0 +1,225 @@
+//! Webhook Simulator
+//!
+//! Builds realistic webhook deliveries, signs them like Nodeless does and
+//! posts them to a local endpoint so handlers can be exercised without a
+//! Nodeless account.
+//!
+//! # Example
+//! ```no_run
+//! use std::str::FromStr;
+//!
+//! use nodeless_rs::amount::Sats;
+//! use nodeless_rs::webhook::WebHookType;
+//! use nodeless_rs::webhook_simulator::{paid_sequence, WebhookSimulator};
+//! use url::Url;
+//!
+//! # async fn run() -> Result<(), nodeless_rs::error::NodelessError> {
+//! let simulator = WebhookSimulator::new(
+//!     Url::from_str("http://localhost:3000/webhook").unwrap(),
+//!     "my-webhook-secret",
+//!     WebHookType::Store,
+//! )?;
+//! let payloads = simulator.lifecycle("invoice-id", Sats(2100), &paid_sequence());
+//! simulator.send_sequence(&payloads).await?;
+//! # Ok(())
+//! # }
+//! ```
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::amount::Sats;
+use crate::error::NodelessError;
+use crate::webhook::{sign_payload, WebHookType, WebhookEvent, WebhookPayload, SIGNATURE_HEADER};
+
+/// Webhook Simulator
+#[derive(Debug, Clone)]
+pub struct WebhookSimulator {
+    client: Client,
+    url: Url,
+    secret: String,
+    type_: WebHookType,
+    metadata: Option<Value>,
+}
+
+impl WebhookSimulator {
+    /// Create simulator
+    /// # Arguments
+    /// * `url` - Endpoint receiving the deliveries
+    /// * `secret` - Secret used to sign the deliveries
+    /// * `type_` - Type of webhook to simulate
+    pub fn new(url: Url, secret: &str, type_: WebHookType) -> Result<Self, NodelessError> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            url,
+            secret: secret.to_string(),
+            type_,
+            metadata: None,
+        })
+    }
+
+    /// Set metadata sent with every payload
+    ///
+    /// Without it store payloads carry an empty object and paywall payloads an
+    /// empty list, like invoices and requests created without metadata.
+    pub fn with_metadata(mut self, metadata: Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Type of webhook simulated
+    pub fn webhook_type(&self) -> &WebHookType {
+        &self.type_
+    }
+
+    /// Build payload of `event` for invoice or request `uuid`
+    ///
+    /// The metadata follows the simulated webhook type unless set with
+    /// [`with_metadata`](Self::with_metadata).
+    pub fn payload(&self, uuid: &str, sats_amount: Sats, event: WebhookEvent) -> WebhookPayload {
+        let now = chrono::Utc::now().timestamp();
+        let paid_at = match event {
+            WebhookEvent::Paid | WebhookEvent::Overpaid => Some(now),
+            _ => None,
+        };
+        let metadata = self.metadata.clone().or_else(|| match self.type_ {
+            WebHookType::Store => Some(json!({})),
+            WebHookType::Paywall => Some(json!([])),
+            _ => None,
+        });
+
+        WebhookPayload {
+            uuid: uuid.to_string(),
+            status: event,
+            sats_amount: Some(sats_amount),
+            metadata,
+            created_at: Some(now),
+            paid_at,
+        }
+    }
+
+    /// Build payloads of `events` in order for invoice or request `uuid`
+    pub fn lifecycle(
+        &self,
+        uuid: &str,
+        sats_amount: Sats,
+        events: &[WebhookEvent],
+    ) -> Vec<WebhookPayload> {
+        events
+            .iter()
+            .map(|event| self.payload(uuid, sats_amount, event.clone()))
+            .collect()
+    }
+
+    /// Sign and post `payload`
+    ///
+    /// Returns the status code of the receiving endpoint.
+    pub async fn send(&self, payload: &WebhookPayload) -> Result<StatusCode, NodelessError> {
+        let (body, signature) = self.sign(payload)?;
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .header(SIGNATURE_HEADER, signature)
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        Ok(res.status())
+    }
+
+    /// Serialize `payload` and sign the body
+    ///
+    /// Returns the body and the value of the [`SIGNATURE_HEADER`] header.
+    pub fn sign(&self, payload: &WebhookPayload) -> Result<(Vec<u8>, String), NodelessError> {
+        let body = serde_json::to_vec(payload)?;
+        let signature = sign_payload(&self.secret, &body);
+        Ok((body, signature))
+    }
+
+    /// Sign and post `payloads` one after another
+    pub async fn send_sequence(
+        &self,
+        payloads: &[WebhookPayload],
+    ) -> Result<Vec<StatusCode>, NodelessError> {
+        let mut statuses = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            statuses.push(self.send(payload).await?);
+        }
+        Ok(statuses)
+    }
+}
+
+/// Events of a typical paid invoice
+pub fn paid_sequence() -> Vec<WebhookEvent> {
+    vec![
+        WebhookEvent::New,
+        WebhookEvent::PendingConfirmation,
+        WebhookEvent::Paid,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::webhook::WebhookVerifier;
+
+    fn simulator(type_: WebHookType) -> WebhookSimulator {
+        WebhookSimulator::new(
+            Url::from_str("http://localhost:3000/webhook").unwrap(),
+            "secret",
+            type_,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn metadata_follows_webhook_type() {
+        let metadata = |type_| {
+            simulator(type_)
+                .payload("id", Sats(2100), WebhookEvent::New)
+                .metadata
+        };
+        assert_eq!(metadata(WebHookType::Store), Some(json!({})));
+        assert_eq!(metadata(WebHookType::DonationPage), None);
+        assert_eq!(metadata(WebHookType::Paywall), Some(json!([])));
+        assert_eq!(metadata(WebHookType::Inbox), None);
+    }
+
+    #[test]
+    fn custom_metadata_is_sent() {
+        let simulator = simulator(WebHookType::Store).with_metadata(json!({"orderId": "42"}));
+        let payload = simulator.payload("id", Sats(2100), WebhookEvent::New);
+        assert_eq!(payload.metadata, Some(json!({"orderId": "42"})));
+    }
+
+    #[test]
+    fn lifecycle_sets_paid_at_on_payment() {
+        let payloads = simulator(WebHookType::Store).lifecycle("id", Sats(2100), &paid_sequence());
+
+        let events: Vec<_> = payloads.iter().map(|p| p.status.clone()).collect();
+        assert_eq!(events, paid_sequence());
+        assert!(payloads.iter().all(|p| p.uuid == "id"));
+        assert!(payloads.iter().all(|p| p.sats_amount == Some(Sats(2100))));
+        assert!(payloads[0].paid_at.is_none());
+        assert!(payloads[1].paid_at.is_none());
+        assert!(payloads[2].paid_at.is_some());
+    }
+
+    #[test]
+    fn signed_payload_verifies() {
+        let simulator = simulator(WebHookType::Paywall);
+        let payload = simulator.payload("id", Sats(2100), WebhookEvent::Paid);
+        let (body, signature) = simulator.sign(&payload).unwrap();
+
+        let delivery = WebhookVerifier::new(WebHookType::Paywall, "secret")
+            .verify(&body, &signature)
+            .unwrap();
+        assert_eq!(delivery.type_, WebHookType::Paywall);
+        assert_eq!(delivery.payload.uuid, "id");
+        assert_eq!(delivery.event(), &WebhookEvent::Paid);
+    }
+}